@@ -0,0 +1,378 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+use crate::field::{Field, FieldSet};
+
+/// What kind of update bumped the version, from the script's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChangeKind {
+    /// Another session became the current one.
+    Session,
+    /// The track metadata went away.
+    Cleared,
+    /// A different track, or known metadata replaced.
+    Track,
+    /// Metadata that was missing arrived for the same track, e.g. genres or artwork.
+    Enriched,
+    /// Playing, paused, stopped and so on.
+    Status,
+    /// Shuffle, repeat or rate.
+    Settings,
+}
+
+impl ChangeKind {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Session => "session",
+            ChangeKind::Cleared => "cleared",
+            ChangeKind::Track => "track",
+            ChangeKind::Enriched => "enriched",
+            ChangeKind::Status => "status",
+            ChangeKind::Settings => "settings",
+        }
+    }
+}
+
+/// One version bump: the version it produced, its kind and the fields it touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Change {
+    pub(crate) version: u64,
+    pub(crate) kind: ChangeKind,
+    pub(crate) fields: FieldSet,
+    // Artwork is not a field, but scripts may still want to reload it
+    pub(crate) thumbnail: bool,
+}
+
+// Track metadata, the duration included
+fn is_metadata(field: Field) -> bool {
+    field.is_track() || field == Field::Duration
+}
+
+/// Collects what an update changed so it can be classified once it is done.
+#[derive(Debug, Default)]
+pub(crate) struct Diff {
+    pub(crate) fields: FieldSet,
+    // Known metadata got a different value or went away
+    pub(crate) replaced: bool,
+    pub(crate) thumbnail: bool,
+    // Set once the update is done, from what is left of the metadata
+    pub(crate) cleared: bool,
+}
+
+impl Diff {
+    /// Stores `new` in `slot`, noting `field` if that changed anything.
+    pub(crate) fn merge<T: PartialEq>(
+        &mut self,
+        field: Field,
+        slot: &mut Option<T>,
+        new: Option<T>,
+    ) {
+        if *slot != new {
+            self.replaced |= slot.is_some() && is_metadata(field);
+            self.fields.insert(field);
+            *slot = new;
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fields.is_empty() && !self.thumbnail
+    }
+
+    /// Folds a later diff into this one, as if both updates had been one.
+    pub(crate) fn absorb(&mut self, later: Diff) {
+        self.fields = self.fields.union(later.fields);
+        self.replaced |= later.replaced;
+        self.thumbnail |= later.thumbnail;
+    }
+
+    // Whether any metadata changed
+    pub(crate) fn touches_track(&self) -> bool {
+        self.thumbnail || self.fields.iter().any(is_metadata)
+    }
+
+    // The most telling kind wins: a session switch usually changes everything else too
+    pub(crate) fn kind(&self) -> ChangeKind {
+        if self.fields.contains(Field::AppId) {
+            ChangeKind::Session
+        } else if self.cleared {
+            ChangeKind::Cleared
+        } else if self.replaced || self.fields.contains(Field::Title) {
+            ChangeKind::Track
+        } else if self.touches_track() {
+            ChangeKind::Enriched
+        } else if self.fields.contains(Field::Status) {
+            ChangeKind::Status
+        } else {
+            ChangeKind::Settings
+        }
+    }
+}
+
+/// The fields a waiter cares about; changes to anything else pass it by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ChangeFilter {
+    // Nothing listed lets every change through
+    fields: FieldSet,
+    thumbnail: bool,
+}
+
+impl ChangeFilter {
+    pub(crate) fn matches(&self, change: &Change) -> bool {
+        (self.fields.is_empty() && !self.thumbnail)
+            || change.fields.iter().any(|f| self.fields.contains(f))
+            || (self.thumbnail && change.thumbnail)
+    }
+}
+
+/// What `wait_for_media` and `next_event` take: an optional last-seen version
+/// followed by field names, e.g. `12 title,artist` or just `status`. For
+/// `wait_for_media` an `id=<name>` word may come first, e.g. `id=np 12 title`,
+/// and a `timeout=<ms>` word anywhere after it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct WaitSpec {
+    // Lowercase; anonymous callers share one listener
+    pub(crate) listener: Option<String>,
+    pub(crate) seen: Option<u64>,
+    pub(crate) filter: ChangeFilter,
+    // Waits forever when unset
+    pub(crate) timeout: Option<Duration>,
+}
+
+impl WaitSpec {
+    pub(crate) fn parse(spec: &str) -> Result<WaitSpec, String> {
+        let mut parsed = WaitSpec::default();
+        for (i, word) in spec.split_whitespace().enumerate() {
+            // The version comes first, or right after the listener id
+            let at_version = i == 0 || (i == 1 && parsed.listener.is_some());
+            if at_version && let Ok(seen) = word.parse() {
+                parsed.seen = Some(seen);
+                continue;
+            }
+            if i == 0
+                && let Some(id) = word.strip_prefix("id=")
+            {
+                if id.is_empty() {
+                    return Err("empty listener id".to_string());
+                }
+                parsed.listener = Some(id.to_ascii_lowercase());
+                continue;
+            }
+            if let Some(millis) = word.strip_prefix("timeout=") {
+                let millis = millis
+                    .parse()
+                    .map_err(|_| format!("bad timeout '{millis}'"))?;
+                parsed.timeout = Some(Duration::from_millis(millis));
+                continue;
+            }
+            for name in word.split(',').filter(|name| !name.is_empty()) {
+                if name.eq_ignore_ascii_case("thumbnail") {
+                    parsed.filter.thumbnail = true;
+                    continue;
+                }
+                let field = Field::parse(name).ok_or_else(|| format!("unknown field '{name}'"))?;
+                parsed.filter.fields.insert(field);
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+// How many changes stay around for scripts that fell behind
+const EVENT_CAPACITY: usize = 64;
+
+/// The most recent changes, oldest first. Versions double as sequence numbers:
+/// every change gets the next one.
+#[derive(Debug, Default)]
+pub(crate) struct EventLog {
+    events: VecDeque<Change>,
+}
+
+impl EventLog {
+    pub(crate) fn push(&mut self, change: Change) {
+        if self.events.len() == EVENT_CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(change);
+    }
+
+    pub(crate) fn latest(&self) -> Option<Change> {
+        self.events.back().copied()
+    }
+
+    /// The first change after version `seen` that `filter` lets through. When the
+    /// changes right after `seen` already dropped out, the jump in versions shows
+    /// what was lost. A `seen` from the future means the DLL was reloaded, so every
+    /// change kept is news.
+    pub(crate) fn after(&self, seen: u64, filter: &ChangeFilter) -> Option<Change> {
+        let latest = self.latest()?;
+        let seen = if seen > latest.version { 0 } else { seen };
+        self.events
+            .iter()
+            .find(|c| c.version > seen && filter.matches(c))
+            .copied()
+    }
+}
+
+// "<version> <kind> <field,field,...>", as the wait_for_media callback gets it
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.version, self.kind.as_str(), self.fields)?;
+        if self.thumbnail {
+            f.write_str(if self.fields.is_empty() {
+                "thumbnail"
+            } else {
+                ",thumbnail"
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(version: u64) -> Change {
+        Change {
+            version,
+            kind: ChangeKind::Track,
+            fields: [Field::Title].into_iter().collect(),
+            thumbnail: false,
+        }
+    }
+
+    fn versions(log: &EventLog, seen: &[u64]) -> Vec<Option<u64>> {
+        seen.iter()
+            .map(|&seen| log.after(seen, &ChangeFilter::default()).map(|c| c.version))
+            .collect()
+    }
+
+    #[test]
+    fn log_returns_the_first_change_after_a_version() {
+        let mut log = EventLog::default();
+        assert_eq!(versions(&log, &[0]), [None]);
+        for version in 1..=3 {
+            log.push(change(version));
+        }
+        assert_eq!(
+            versions(&log, &[0, 1, 2, 3]),
+            [Some(1), Some(2), Some(3), None]
+        );
+        // Reloaded DLLs start counting again; a caller from before gets everything
+        assert_eq!(versions(&log, &[40]), [Some(1)]);
+    }
+
+    #[test]
+    fn log_keeps_only_the_latest_changes() {
+        let mut log = EventLog::default();
+        for version in 1..=100 {
+            log.push(change(version));
+        }
+        assert_eq!(log.latest(), Some(change(100)));
+        // The oldest kept change tells how much was missed
+        assert_eq!(
+            versions(&log, &[0, 36, 37, 99]),
+            [Some(37), Some(37), Some(38), Some(100)]
+        );
+    }
+
+    #[test]
+    fn filters_skip_changes_to_other_fields() {
+        let mut log = EventLog::default();
+        let status = Change {
+            version: 2,
+            kind: ChangeKind::Status,
+            fields: [Field::Status].into_iter().collect(),
+            thumbnail: false,
+        };
+        let artwork = Change {
+            version: 3,
+            kind: ChangeKind::Enriched,
+            fields: FieldSet::default(),
+            thumbnail: true,
+        };
+        for change in [change(1), status, artwork, change(4)] {
+            log.push(change);
+        }
+
+        let after = |seen, spec| {
+            let filter = WaitSpec::parse(spec).unwrap().filter;
+            log.after(seen, &filter).map(|c| c.version)
+        };
+        assert_eq!(after(0, "title,artist"), Some(1));
+        assert_eq!(after(1, "title,artist"), Some(4));
+        assert_eq!(after(4, "title,artist"), None);
+        assert_eq!(after(0, "status"), Some(2));
+        assert_eq!(after(2, "status"), None);
+        assert_eq!(after(1, "thumbnail"), Some(3));
+        assert_eq!(after(1, "status thumbnail"), Some(2));
+        assert_eq!(after(2, ""), Some(3));
+    }
+
+    #[test]
+    fn wait_specs_take_a_version_and_fields() {
+        let spec = WaitSpec::parse(" 12 title,Artist ").unwrap();
+        assert_eq!(spec.seen, Some(12));
+        assert!(spec.filter.fields.contains(Field::Artist));
+        assert_eq!(spec.filter.fields.to_string(), "title,artist");
+
+        let spec = WaitSpec::parse("status").unwrap();
+        assert_eq!(spec.seen, None);
+        assert_eq!(spec.filter.fields.to_string(), "status");
+
+        assert_eq!(WaitSpec::parse("").unwrap(), WaitSpec::default());
+        assert_eq!(WaitSpec::parse("status").unwrap().listener, None);
+        let spec = WaitSpec::parse("12 timeout=1500 title").unwrap();
+        assert_eq!(spec.seen, Some(12));
+        assert_eq!(spec.timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(spec.filter.fields.to_string(), "title");
+        assert_eq!(
+            WaitSpec::parse("timeout=soon"),
+            Err("bad timeout 'soon'".to_string())
+        );
+        assert_eq!(
+            WaitSpec::parse("title 12"),
+            Err("unknown field '12'".to_string())
+        );
+        assert_eq!(
+            WaitSpec::parse("title,nope"),
+            Err("unknown field 'nope'".to_string())
+        );
+    }
+
+    #[test]
+    fn wait_specs_may_start_with_a_listener_id() {
+        let spec = WaitSpec::parse("id=NP 12 title timeout=500").unwrap();
+        assert_eq!(spec.listener.as_deref(), Some("np"));
+        assert_eq!(spec.seen, Some(12));
+        assert_eq!(spec.filter.fields.to_string(), "title");
+        assert_eq!(spec.timeout, Some(Duration::from_millis(500)));
+
+        let spec = WaitSpec::parse("id=np status").unwrap();
+        assert_eq!(spec.listener.as_deref(), Some("np"));
+        assert_eq!(spec.seen, None);
+
+        // Only the explicit form names a listener, so a misspelt field is an error
+        assert_eq!(
+            WaitSpec::parse("titel"),
+            Err("unknown field 'titel'".to_string())
+        );
+        assert_eq!(WaitSpec::parse("id="), Err("empty listener id".to_string()));
+        assert_eq!(
+            WaitSpec::parse("12 id=np"),
+            Err("unknown field 'id=np'".to_string())
+        );
+    }
+
+    #[test]
+    fn changes_read_as_callback_data() {
+        let mut change = change(7);
+        assert_eq!(change.to_string(), "7 track title");
+        change.thumbnail = true;
+        assert_eq!(change.to_string(), "7 track title,thumbnail");
+        change.fields = FieldSet::default();
+        change.kind = ChangeKind::Enriched;
+        assert_eq!(change.to_string(), "7 enriched thumbnail");
+    }
+}
@@ -0,0 +1,692 @@
+use std::time::{Duration, SystemTime};
+
+use mirust::{Call, Client, Config, Unload, UnloadReason};
+
+use crate::event::WaitSpec;
+use crate::field::{Field, FieldList};
+use crate::policy::parse_switch;
+use crate::source::{Command, SourceEvent};
+use crate::state::{
+    MediaState, QueryMode, RepeatMode, SessionSnapshot, WaitOutcome, ensure_state, find_session,
+};
+use crate::template::Template;
+use crate::timeline::{Timeline, time_text};
+use crate::watcher::{start_media_watcher, stop_media_watcher};
+
+// How long a transport command may take before the export gives up on the player
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long a query waits for the player while nobody is listening
+const FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+mirust::config!(Config::new().on_unload(unloading));
+
+mirust::export!(
+    wait_for_media,
+    settle,
+    clear_grace,
+    next_event,
+    halt,
+    query,
+    health,
+    diag,
+    sessions,
+    status,
+    position,
+    duration,
+    remaining,
+    play,
+    pause,
+    toggle,
+    next,
+    previous,
+    stop,
+    seek,
+    shuffle,
+    repeat,
+    rate,
+    source,
+    source_name,
+    get,
+    json,
+    format,
+    format_option,
+    select_session,
+    session_policy,
+    title,
+    albumartist,
+    albumtitle,
+    genres,
+    playbacktype,
+    subtitle,
+    tracknumber,
+    albumtrackcount,
+    thumbnail,
+    artist,
+    version,
+);
+
+// mIRC unloading the DLL halts everything, even when the script did not, so no
+// thread is left running in code that is gone. Idle unloads are refused, as the
+// watcher and the settings would be lost.
+fn unloading(reason: UnloadReason) -> Unload {
+    match reason {
+        UnloadReason::Idle => Unload::Keep,
+        _ => {
+            halt_all();
+            Unload::Allow
+        }
+    }
+}
+
+// Stops listening, releases every waiting call and ends the watcher thread
+fn halt_all() {
+    ensure_state().halt();
+    stop_media_watcher();
+}
+
+// Whether the watcher is there for the track functions and commands: always
+// while listening, otherwise only in fetch mode, where it is started on demand
+// and ends by itself once nobody has asked for a while
+fn watcher_available() -> bool {
+    let media = ensure_state();
+    if media.is_listening() {
+        return true;
+    }
+    if media.lock().query == QueryMode::Live {
+        return false;
+    }
+    media.note_query();
+    start_media_watcher();
+    true
+}
+
+// Whether the track functions have anything to report: always while listening,
+// otherwise only in fetch mode, once the player has been read
+fn readable() -> bool {
+    let media = ensure_state();
+    media.is_listening() || (watcher_available() && media.fetch(FETCH_TIMEOUT))
+}
+
+// Sends `command` to the selected session and reports the outcome the way halt does
+fn control_result(command: Command) -> String {
+    if !watcher_available() {
+        "E_FAIL not listening".to_string()
+    } else {
+        match ensure_state().control(command, CONTROL_TIMEOUT) {
+            Ok(()) => "S_OK".to_string(),
+            Err(err) => format!("E_FAIL {}", err),
+        }
+    }
+}
+
+// Returns `field` for the current track, or for the session named by `data`
+// (1-based index or source app id) when one is given
+fn field_result(field: Field, data: &str) -> String {
+    if !readable() {
+        return String::new();
+    }
+    let state = ensure_state().lock();
+    if data.trim().is_empty() {
+        field.text(&state.media)
+    } else {
+        find_session(&state.sessions, data)
+            .and_then(|session| session.media.as_ref())
+            .map(|media| field.text(media))
+            .unwrap_or_default()
+    }
+}
+
+// Reports a playback setting when `data` is empty, otherwise asks the player to
+// change it to the value `parse` makes of `data`
+fn setting_result(
+    data: &str,
+    get: fn(&MediaState) -> Option<String>,
+    parse: fn(&str, &MediaState) -> Option<Command>,
+) -> String {
+    let data = data.trim();
+    if data.is_empty() {
+        let value = if readable() {
+            get(&ensure_state().lock()).unwrap_or_default()
+        } else {
+            String::new()
+        };
+        return value;
+    }
+    let command = parse(data, &ensure_state().lock());
+    match command {
+        Some(command) => control_result(command),
+        None => format!("E_INVALIDARG unexpected value '{}'", data),
+    }
+}
+
+// Reads a point on the timeline of the current track, or of the session named by
+// `data`, extrapolated to now
+fn timeline_result(data: &str, pick: fn(&Timeline, SystemTime, f64) -> Option<Duration>) -> String {
+    if !readable() {
+        return String::new();
+    }
+    let media = ensure_state();
+    let now = media.now();
+    let state = media.lock();
+    let value = if data.trim().is_empty() {
+        state
+            .timeline
+            .and_then(|timeline| pick(&timeline, now, state.speed()))
+    } else {
+        find_session(&state.sessions, data).and_then(|session| {
+            session
+                .timeline
+                .and_then(|timeline| pick(&timeline, now, session.speed()))
+        })
+    };
+
+    value.map(time_text).unwrap_or_default()
+}
+
+// index<TAB>app id<TAB>status<TAB>title<TAB>artist
+fn session_line(index: usize, session: &SessionSnapshot) -> String {
+    let (title, artist) = session
+        .media
+        .as_ref()
+        .map(|m| (Field::Title.text(m), Field::Artist.text(m)))
+        .unwrap_or_default();
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        index + 1,
+        session.app_id,
+        session.status.map(|s| s.as_str()).unwrap_or(""),
+        title,
+        artist
+    )
+}
+
+// Blocks until there is a change, so only ever called through $dllcall
+fn wait_for_media(call: Call) -> String {
+    let data = call.into_data();
+    // The last version the script saw, so nothing after it is missed, and the
+    // fields it wants to hear about
+    let spec = match WaitSpec::parse(&data) {
+        Ok(spec) => spec,
+        Err(err) => {
+            return format!("E_INVALIDARG {}", err);
+        }
+    };
+    let media = ensure_state();
+    media.set_listening(true);
+    start_media_watcher();
+
+    // The callback alias gets "<version> <kind> <fields>" in $1-, or the version
+    // followed by why it was released without a change
+    match media.wait_for_change(spec) {
+        WaitOutcome::Changed(change) => change.to_string(),
+        WaitOutcome::Cancelled => format!("{} halted", media.lock().version),
+        WaitOutcome::TimedOut => format!("{} timeout", media.lock().version),
+    }
+}
+
+// Longest settle window or clear grace period a script may ask for
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+// Reports a delay setting in milliseconds when `data` is empty, otherwise sets it
+fn delay_result(data: &str, slot: fn(&mut MediaState) -> &mut Duration) -> String {
+    let data = data.trim();
+    let mut state = ensure_state().lock();
+    let delay = slot(&mut state);
+    if data.is_empty() {
+        delay.as_millis().to_string()
+    } else {
+        match data.parse().map(Duration::from_millis) {
+            Ok(value) if value <= MAX_DELAY => {
+                *delay = value;
+                value.as_millis().to_string()
+            }
+            _ => format!(
+                "E_INVALIDARG expected milliseconds up to {}, got '{}'",
+                MAX_DELAY.as_millis(),
+                data
+            ),
+        }
+    }
+}
+
+// Reads or sets how long changes wait for more to follow, in milliseconds
+fn settle(call: Call) -> String {
+    let data = call.into_data();
+    delay_result(&data, |state| &mut state.settle)
+}
+
+// Reads or sets how long the player may report no track before the metadata is
+// cleared, in milliseconds
+fn clear_grace(call: Call) -> String {
+    let data = call.into_data();
+    delay_result(&data, |state| &mut state.clear_grace)
+}
+
+// Returns the first queued change after the version given (the oldest one kept
+// when none is) to the fields given, or nothing once the caller has caught up
+fn next_event(call: Call) -> String {
+    let data = call.into_data();
+    match WaitSpec::parse(&data) {
+        // Only wait_for_media registers listeners
+        Ok(spec) if spec.listener.is_some() => {
+            "E_INVALIDARG next_event does not take a listener id".to_string()
+        }
+        Ok(spec) => ensure_state()
+            .lock()
+            .events
+            .after(spec.seen.unwrap_or(0), &spec.filter)
+            .map(|change| change.to_string())
+            .unwrap_or_default(),
+        Err(err) => format!("E_INVALIDARG {}", err),
+    }
+}
+
+fn halt(call: Call) -> String {
+    let data = call.into_data();
+    // With a listener id only that listener is released; the id= is optional here
+    let id = data.trim();
+    let id = id.strip_prefix("id=").unwrap_or(id);
+    if id.is_empty() {
+        // Also unsubscribes from the player; the next wait_for_media starts again
+        halt_all();
+        "S_OK".to_string()
+    } else if ensure_state().halt_listener(id) {
+        // That was the last listener, so nothing needs the watcher any more
+        if !ensure_state().is_listening() {
+            stop_media_watcher();
+        }
+        "S_OK".to_string()
+    } else {
+        format!("E_INVALIDARG unknown listener '{}'", id)
+    }
+}
+
+// Reads or sets what the track functions do while nobody is listening
+fn query(call: Call) -> String {
+    let data = call.into_data();
+    let data = data.trim();
+    let mut state = ensure_state().lock();
+    if data.is_empty() {
+        state.query.as_str().to_string()
+    } else {
+        match QueryMode::parse(data) {
+            Some(mode) => {
+                state.query = mode;
+                mode.as_str().to_string()
+            }
+            None => format!("E_INVALIDARG expected live or fetch, got '{}'", data),
+        }
+    }
+}
+
+// Reports how the background thread is doing, whether or not anyone is listening
+fn health(_call: Call) -> String {
+    ensure_state().lock().health.to_string()
+}
+
+// Everything worth pasting into a bug report, on one line
+fn diag(_call: Call) -> String {
+    let data = format!(
+        "{} {} {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        ensure_state().diagnostics()
+    );
+    data
+}
+
+fn sessions(_call: Call) -> String {
+    if !readable() {
+        return String::new();
+    }
+    let state = ensure_state().lock();
+    state
+        .sessions
+        .iter()
+        .enumerate()
+        .map(|(i, session)| session_line(i, session))
+        .collect::<Vec<_>>()
+        .join("\u{1}")
+}
+
+// Playback status of the current track, or of the session named by `data`
+fn status(call: Call) -> String {
+    let data = call.into_data();
+    if !readable() {
+        return String::new();
+    }
+    let state = ensure_state().lock();
+    let status = if data.trim().is_empty() {
+        state.status
+    } else {
+        find_session(&state.sessions, &data).and_then(|session| session.status)
+    };
+
+    status.map(|s| s.as_str()).unwrap_or("").to_string()
+}
+
+fn position(call: Call) -> String {
+    let data = call.into_data();
+    timeline_result(&data, |timeline, now, speed| {
+        Some(timeline.position_at(now, speed))
+    })
+}
+
+fn duration(call: Call) -> String {
+    let data = call.into_data();
+    timeline_result(&data, |timeline, _, _| timeline.duration)
+}
+
+fn remaining(call: Call) -> String {
+    let data = call.into_data();
+    timeline_result(&data, Timeline::remaining_at)
+}
+
+fn play(_call: Call) -> String {
+    control_result(Command::Play)
+}
+
+fn pause(_call: Call) -> String {
+    control_result(Command::Pause)
+}
+
+fn toggle(_call: Call) -> String {
+    control_result(Command::Toggle)
+}
+
+fn next(_call: Call) -> String {
+    control_result(Command::Next)
+}
+
+fn previous(_call: Call) -> String {
+    control_result(Command::Previous)
+}
+
+fn stop(_call: Call) -> String {
+    control_result(Command::Stop)
+}
+
+// Jumps to `data` seconds into the track
+fn seek(call: Call) -> String {
+    let data = call.into_data();
+    // Rejects negative, non-finite and too large positions alike
+    match data
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+    {
+        Some(to) => control_result(Command::Seek(to)),
+        None => format!("E_INVALIDARG expected seconds, got '{}'", data.trim()),
+    }
+}
+
+// on/off, or toggle to flip the current state
+fn shuffle(call: Call) -> String {
+    let data = call.into_data();
+    setting_result(
+        &data,
+        |state| {
+            state
+                .shuffle
+                .map(|on| if on { "on" } else { "off" }.to_string())
+        },
+        |value, state| {
+            let on = if value.eq_ignore_ascii_case("toggle") {
+                !state.shuffle.unwrap_or(false)
+            } else {
+                parse_switch(value).ok()?
+            };
+            Some(Command::Shuffle(on))
+        },
+    )
+}
+
+// none, track or list
+fn repeat(call: Call) -> String {
+    let data = call.into_data();
+    setting_result(
+        &data,
+        |state| state.repeat.map(|mode| mode.as_str().to_string()),
+        |value, _| RepeatMode::parse(value).map(Command::Repeat),
+    )
+}
+
+// Playback speed, 1 being normal
+fn rate(call: Call) -> String {
+    let data = call.into_data();
+    setting_result(
+        &data,
+        |state| state.rate.map(|rate| rate.to_string()),
+        |value, _| {
+            let rate = value.parse::<f64>().ok()?;
+            (rate.is_finite() && rate > 0.0).then_some(Command::Rate(rate))
+        },
+    )
+}
+
+// app id<TAB>display name of the current track's source, or of the session named by `data`
+fn source(call: Call) -> String {
+    let data = call.into_data();
+    if !readable() {
+        return String::new();
+    }
+    let state = ensure_state().lock();
+    let app_id = if data.trim().is_empty() {
+        state.app_id.as_deref()
+    } else {
+        find_session(&state.sessions, &data).map(|session| session.app_id.as_str())
+    };
+    app_id
+        .map(|app_id| format!("{}\t{}", app_id, state.names.resolve(app_id)))
+        .unwrap_or_default()
+}
+
+// `app=Name` names an app (an empty name forgets it); a bare app id returns its name
+fn source_name(call: Call) -> String {
+    let data = call.into_data();
+    let mut state = ensure_state().lock();
+    let app_id = match data.split_once('=') {
+        Some((app_id, name)) => {
+            state.names.set(app_id, name);
+            app_id
+        }
+        None => &data,
+    };
+    state.names.resolve(app_id.trim())
+}
+
+// Several fields from one read of the state, so they always describe the same track
+fn get(call: Call) -> String {
+    let data = call.into_data();
+    match FieldList::parse(&data) {
+        Ok(_) if !readable() => String::new(),
+        Ok(fields) => {
+            let media = ensure_state();
+            let now = media.now();
+            fields.render(&media.lock(), now)
+        }
+        Err(err) => format!("E_INVALIDARG {}", err),
+    }
+}
+
+// Every field, the sessions and the settings as one JSON object, read under a single lock
+fn json(_call: Call) -> String {
+    if readable() {
+        let media = ensure_state();
+        let now = media.now();
+        crate::json::snapshot(&media.lock(), now).to_string()
+    } else {
+        String::new()
+    }
+}
+
+// Fills in a template with the current track, see `Template` for the syntax
+fn format(call: Call) -> String {
+    let data = call.into_data();
+    match Template::parse(&data) {
+        Ok(_) if !readable() => String::new(),
+        Ok(template) => {
+            let media = ensure_state();
+            let now = media.now();
+            let state = media.lock();
+            template.render(&state, now, &state.format)
+        }
+        Err(err) => format!("E_INVALIDARG {}", err),
+    }
+}
+
+// Reads (`key`) or changes (`key=value`) a setting used by format
+fn format_option(call: Call) -> String {
+    let data = call.into_data();
+    ensure_state()
+        .lock()
+        .format
+        .apply(&data)
+        .unwrap_or_else(|err| format!("E_INVALIDARG {}", err))
+}
+
+// Pins the current track to one session (1-based index or source app id) until that
+// session goes away; an empty selector goes back to automatic selection
+fn select_session(call: Call) -> String {
+    let data = call.into_data();
+    let media = ensure_state();
+    let pinned = {
+        let mut state = media.lock();
+        let app_id = if data.trim().is_empty() {
+            None
+        } else {
+            match find_session(&state.sessions, &data) {
+                Some(session) => Some(session.app_id.clone()),
+                None => {
+                    return format!("E_INVALIDARG no session '{}'", data.trim());
+                }
+            }
+        };
+        state.selector.pin(app_id.clone());
+        app_id.unwrap_or_default()
+    };
+    media.notify_watcher(SourceEvent::PolicyChanged);
+
+    pinned
+}
+
+// Updates the session policy from a `key=value` spec and returns the resulting policy
+fn session_policy(call: Call) -> String {
+    let data = call.into_data();
+    let media = ensure_state();
+    let result = {
+        let mut state = media.lock();
+        state
+            .selector
+            .policy
+            .apply(&data)
+            .map(|()| state.selector.policy.to_string())
+    };
+    match result {
+        Ok(policy) => {
+            media.notify_watcher(SourceEvent::PolicyChanged);
+            policy
+        }
+        Err(err) => format!("E_INVALIDARG {}", err),
+    }
+}
+
+fn title(call: Call) -> String {
+    let data = call.into_data();
+    field_result(Field::Title, &data)
+}
+
+fn albumartist(call: Call) -> String {
+    let data = call.into_data();
+    field_result(Field::AlbumArtist, &data)
+}
+
+fn albumtitle(call: Call) -> String {
+    let data = call.into_data();
+    field_result(Field::AlbumTitle, &data)
+}
+
+fn genres(call: Call) -> String {
+    let data = call.into_data();
+    field_result(Field::Genres, &data)
+}
+
+fn playbacktype(call: Call) -> String {
+    let data = call.into_data();
+    field_result(Field::PlaybackType, &data)
+}
+
+fn subtitle(call: Call) -> String {
+    let data = call.into_data();
+    field_result(Field::Subtitle, &data)
+}
+
+fn tracknumber(call: Call) -> String {
+    let data = call.into_data();
+    field_result(Field::TrackNumber, &data)
+}
+
+fn albumtrackcount(call: Call) -> String {
+    let data = call.into_data();
+    field_result(Field::AlbumTrackCount, &data)
+}
+
+fn thumbnail(_call: Call) -> String {
+    if !readable() {
+        return String::new();
+    }
+    let mut state = ensure_state().lock();
+    // If we have a cached file and it exists, return it
+    if let Some(ref path) = state.thumbnail_path {
+        if std::path::Path::new(path).exists() {
+            return path.clone();
+        } else {
+            // Remove stale path
+            state.thumbnail_path = None;
+        }
+    }
+    // If we have thumbnail bytes, write to a temp file and cache the path
+    if let Some(ref bytes) = state.media.thumbnail_bytes {
+        let mut path = std::env::temp_dir();
+        if let Ok(now) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+            path.push(format!("m_nowplaying_thumb_{}.png", now.as_millis()));
+        } else {
+            path.push("m_nowplaying_thumb.png");
+        }
+        if std::fs::write(&path, bytes).is_ok() {
+            let path_str = path.to_string_lossy().to_string();
+            state.thumbnail_path = Some(path_str.clone());
+            return path_str;
+        }
+    }
+    // No thumbnail available
+    String::new()
+}
+
+fn artist(call: Call) -> String {
+    let data = call.into_data();
+    field_result(Field::Artist, &data)
+}
+
+fn version(_call: Call) -> String {
+    let name = env!("CARGO_PKG_NAME");
+    let version = env!("CARGO_PKG_VERSION");
+    let arch = std::env::consts::ARCH;
+    let host = mirust::host();
+    let client = match host.client() {
+        Client::Mirc => "mIRC",
+        _ => "AdiIRC",
+    };
+    let m_version = host.version();
+    format!(
+        "{} {} on {} v{}.{} ({})",
+        name,
+        version,
+        client,
+        m_version.major(),
+        m_version.minor(),
+        arch
+    )
+}
@@ -0,0 +1,386 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use crate::state::{MediaSnapshot, MediaState};
+use crate::timeline::time_text;
+
+/// A piece of track metadata or playback state scripts can ask for by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Field {
+    Title,
+    Artist,
+    AlbumArtist,
+    AlbumTitle,
+    Genres,
+    PlaybackType,
+    Subtitle,
+    TrackNumber,
+    AlbumTrackCount,
+    Status,
+    Position,
+    Duration,
+    Remaining,
+    Shuffle,
+    Repeat,
+    Rate,
+    Source,
+    AppId,
+}
+
+/// A field's value, typed so each output (accessors, templates, JSON) can render it its own way.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Empty,
+    Text(String),
+    List(Vec<String>),
+    Number(u64),
+    Decimal(f64),
+    Time(Duration),
+    Switch(bool),
+}
+
+impl Value {
+    fn text(value: Option<&str>) -> Value {
+        match value.map(str::trim) {
+            Some(s) if !s.is_empty() => Value::Text(s.to_string()),
+            _ => Value::Empty,
+        }
+    }
+}
+
+// The form the accessor exports return
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Empty => Ok(()),
+            Value::Text(s) => f.write_str(s),
+            Value::List(items) => f.write_str(&items.join(", ")),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Decimal(x) => write!(f, "{x}"),
+            Value::Time(time) => f.write_str(&time_text(*time)),
+            Value::Switch(on) => f.write_str(if *on { "on" } else { "off" }),
+        }
+    }
+}
+
+impl Field {
+    pub(crate) const ALL: [Field; 18] = [
+        Field::Title,
+        Field::Artist,
+        Field::AlbumArtist,
+        Field::AlbumTitle,
+        Field::Genres,
+        Field::PlaybackType,
+        Field::Subtitle,
+        Field::TrackNumber,
+        Field::AlbumTrackCount,
+        Field::Status,
+        Field::Position,
+        Field::Duration,
+        Field::Remaining,
+        Field::Shuffle,
+        Field::Repeat,
+        Field::Rate,
+        Field::Source,
+        Field::AppId,
+    ];
+
+    // Names match the accessor exports
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Artist => "artist",
+            Field::AlbumArtist => "albumartist",
+            Field::AlbumTitle => "albumtitle",
+            Field::Genres => "genres",
+            Field::PlaybackType => "playbacktype",
+            Field::Subtitle => "subtitle",
+            Field::TrackNumber => "tracknumber",
+            Field::AlbumTrackCount => "albumtrackcount",
+            Field::Status => "status",
+            Field::Position => "position",
+            Field::Duration => "duration",
+            Field::Remaining => "remaining",
+            Field::Shuffle => "shuffle",
+            Field::Repeat => "repeat",
+            Field::Rate => "rate",
+            Field::Source => "source",
+            Field::AppId => "appid",
+        }
+    }
+
+    pub(crate) fn parse(name: &str) -> Option<Field> {
+        Field::ALL
+            .into_iter()
+            .find(|f| f.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    // Whether the field is track metadata, as opposed to playback state
+    pub(crate) fn is_track(self) -> bool {
+        matches!(
+            self,
+            Field::Title
+                | Field::Artist
+                | Field::AlbumArtist
+                | Field::AlbumTitle
+                | Field::Genres
+                | Field::PlaybackType
+                | Field::Subtitle
+                | Field::TrackNumber
+                | Field::AlbumTrackCount
+        )
+    }
+
+    // Track metadata only; the playback fields live outside `MediaSnapshot`
+    pub(crate) fn track_value(self, media: &MediaSnapshot) -> Value {
+        let number = |n: Option<u32>| n.map_or(Value::Empty, |n| Value::Number(n.into()));
+        match self {
+            Field::Title => Value::text(media.title.as_deref()),
+            Field::Artist => Value::text(media.artist.as_deref()),
+            Field::AlbumArtist => Value::text(media.album_artist.as_deref()),
+            Field::AlbumTitle => Value::text(media.album_title.as_deref()),
+            Field::Genres => match media.genres {
+                Some(ref genres) if !genres.is_empty() => Value::List(genres.clone()),
+                _ => Value::Empty,
+            },
+            // Not trimmed, as the accessor always returned it
+            Field::PlaybackType => media
+                .playback_type
+                .clone()
+                .filter(|s| !s.is_empty())
+                .map_or(Value::Empty, Value::Text),
+            Field::Subtitle => Value::text(media.subtitle.as_deref()),
+            Field::TrackNumber => number(media.track_number),
+            Field::AlbumTrackCount => number(media.album_track_count),
+            _ => Value::Empty,
+        }
+    }
+
+    /// The track metadata value as the accessor exports return it: trimmed, empty when unknown.
+    pub(crate) fn text(self, media: &MediaSnapshot) -> String {
+        self.track_value(media).to_string()
+    }
+
+    /// Reads the field from the current state; time fields are extrapolated to `now`.
+    pub(crate) fn read(self, state: &MediaState, now: SystemTime) -> Value {
+        let time = |time: Option<Duration>| time.map_or(Value::Empty, Value::Time);
+        match self {
+            Field::Status => state
+                .status
+                .map_or(Value::Empty, |s| Value::Text(s.as_str().to_string())),
+            Field::Position => time(state.timeline.map(|t| t.position_at(now, state.speed()))),
+            Field::Duration => time(state.timeline.and_then(|t| t.duration)),
+            Field::Remaining => time(
+                state
+                    .timeline
+                    .and_then(|t| t.remaining_at(now, state.speed())),
+            ),
+            Field::Shuffle => state.shuffle.map_or(Value::Empty, Value::Switch),
+            Field::Repeat => state
+                .repeat
+                .map_or(Value::Empty, |r| Value::Text(r.as_str().to_string())),
+            Field::Rate => state.rate.map_or(Value::Empty, Value::Decimal),
+            Field::Source => state
+                .app_id
+                .as_deref()
+                .map_or(Value::Empty, |id| Value::Text(state.names.resolve(id))),
+            Field::AppId => Value::text(state.app_id.as_deref()),
+            _ => self.track_value(&state.media),
+        }
+    }
+}
+
+/// A set of fields, e.g. the ones an update changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FieldSet(u32);
+
+impl FieldSet {
+    pub(crate) fn insert(&mut self, field: Field) {
+        self.0 |= 1 << field as u32;
+    }
+
+    pub(crate) fn contains(self, field: Field) -> bool {
+        self.0 & (1 << field as u32) != 0
+    }
+
+    pub(crate) fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub(crate) fn union(self, other: FieldSet) -> FieldSet {
+        FieldSet(self.0 | other.0)
+    }
+
+    pub(crate) fn iter(self) -> impl Iterator<Item = Field> {
+        Field::ALL.into_iter().filter(move |&f| self.contains(f))
+    }
+}
+
+impl FromIterator<Field> for FieldSet {
+    fn from_iter<I: IntoIterator<Item = Field>>(fields: I) -> Self {
+        let mut set = FieldSet::default();
+        for field in fields {
+            set.insert(field);
+        }
+        set
+    }
+}
+
+// Field names separated by commas
+impl fmt::Display for FieldSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, field) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(field.name())?;
+        }
+        Ok(())
+    }
+}
+
+/// The fields asked for by the `get` export, e.g. `sep=9 title artist`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FieldList {
+    // Goes between values; $chr(1) unless `sep=<code>` says otherwise
+    separator: char,
+    fields: Vec<Field>,
+}
+
+impl FieldList {
+    pub(crate) fn parse(spec: &str) -> Result<FieldList, String> {
+        let mut separator = '\x01';
+        let mut fields = Vec::new();
+        for (i, word) in spec.split_whitespace().enumerate() {
+            if i == 0
+                && let Some(code) = word.strip_prefix("sep=")
+            {
+                separator = code
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&code| code != 0)
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("bad separator '{code}'"))?;
+                continue;
+            }
+            fields.push(Field::parse(word).ok_or_else(|| format!("unknown field '{word}'"))?);
+        }
+        if fields.is_empty() {
+            return Err("no fields given".to_string());
+        }
+        Ok(FieldList { separator, fields })
+    }
+
+    /// The values as the accessor exports return them, joined by the separator.
+    pub(crate) fn render(&self, state: &MediaState, now: SystemTime) -> String {
+        let mut sep = [0; 4];
+        let sep: &str = self.separator.encode_utf8(&mut sep);
+        self.fields
+            .iter()
+            .map(|field| field.read(state, now).to_string())
+            .collect::<Vec<_>>()
+            .join(sep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::state::PlaybackStatus;
+    use crate::timeline::Timeline;
+
+    #[test]
+    fn names_round_trip() {
+        for field in Field::ALL {
+            assert_eq!(Field::parse(field.name()), Some(field));
+        }
+        assert_eq!(Field::parse(" Title "), Some(Field::Title));
+        assert_eq!(Field::parse("thumbnail"), None);
+    }
+
+    #[test]
+    fn text_matches_accessor_formatting() {
+        let media = MediaSnapshot {
+            title: Some("  Song ".to_string()),
+            genres: Some(vec!["Rock".to_string(), "Pop".to_string()]),
+            track_number: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(Field::Title.text(&media), "Song");
+        assert_eq!(Field::Artist.text(&media), "");
+        assert_eq!(Field::Genres.text(&media), "Rock, Pop");
+        assert_eq!(Field::TrackNumber.text(&media), "4");
+        assert_eq!(Field::AlbumTrackCount.text(&media), "");
+    }
+
+    #[test]
+    fn playback_fields_read_from_state() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut state = MediaState {
+            status: Some(PlaybackStatus::Playing),
+            timeline: Some(Timeline {
+                position: Duration::from_secs(60),
+                duration: Some(Duration::from_secs(200)),
+                updated: now - Duration::from_secs(20),
+            }),
+            shuffle: Some(true),
+            app_id: Some("Spotify.exe".to_string()),
+            ..Default::default()
+        };
+        state.media.title = Some(" Song ".to_string());
+
+        let read = |field: Field| field.read(&state, now);
+        assert_eq!(read(Field::Title), Value::Text("Song".to_string()));
+        assert_eq!(read(Field::Status), Value::Text("playing".to_string()));
+        assert_eq!(read(Field::Position), Value::Time(Duration::from_secs(80)));
+        assert_eq!(
+            read(Field::Remaining),
+            Value::Time(Duration::from_secs(120))
+        );
+        assert_eq!(read(Field::Shuffle).to_string(), "on");
+        assert_eq!(read(Field::Repeat), Value::Empty);
+        assert_eq!(read(Field::Source), Value::Text("Spotify".to_string()));
+        assert_eq!(read(Field::AppId).to_string(), "Spotify.exe");
+        assert_eq!(read(Field::Duration).to_string(), "200 3:20");
+    }
+
+    #[test]
+    fn field_sets_list_names_in_order() {
+        let set: FieldSet = [Field::Status, Field::Title, Field::AppId]
+            .into_iter()
+            .collect();
+        assert!(set.contains(Field::Title));
+        assert!(!set.contains(Field::Artist));
+        assert_eq!(set.to_string(), "title,status,appid");
+        assert!(FieldSet::default().is_empty());
+        assert_eq!(FieldSet::default().to_string(), "");
+    }
+
+    #[test]
+    fn field_lists_join_values_with_the_separator() {
+        let now = SystemTime::UNIX_EPOCH;
+        let mut state = MediaState {
+            shuffle: Some(false),
+            ..Default::default()
+        };
+        state.media.title = Some("Song".to_string());
+        state.media.artist = Some("Band".to_string());
+
+        let get = |spec| FieldList::parse(spec).unwrap().render(&state, now);
+        assert_eq!(get("title artist"), "Song\x01Band");
+        assert_eq!(get("sep=9 Title albumtitle shuffle"), "Song\t\toff");
+        assert_eq!(get("sep=124  artist"), "Band");
+    }
+
+    #[test]
+    fn field_lists_reject_bad_specs() {
+        let error = |spec| FieldList::parse(spec).unwrap_err();
+        assert_eq!(error(""), "no fields given");
+        assert_eq!(error("sep=9"), "no fields given");
+        assert_eq!(error("title sep=9"), "unknown field 'sep=9'");
+        assert_eq!(error("sep=0 title"), "bad separator '0'");
+        assert_eq!(error("sep=, title"), "bad separator ','");
+        assert_eq!(error("title nope"), "unknown field 'nope'");
+    }
+}
@@ -0,0 +1,294 @@
+use std::fmt::{self, Write};
+use std::time::{Duration, SystemTime};
+
+use crate::field::{Field, Value};
+use crate::state::{MediaState, SessionSnapshot};
+
+/// Bumped whenever a key is renamed, removed or changes type. Adding keys does not bump it.
+pub(crate) const SCHEMA_VERSION: u64 = 1;
+
+/// Just enough JSON to write snapshots out; keys keep their insertion order.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Int(u64),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl From<Value> for Json {
+    fn from(value: Value) -> Json {
+        match value {
+            Value::Empty => Json::Null,
+            Value::Text(text) => Json::String(text),
+            Value::List(items) => Json::Array(items.into_iter().map(Json::String).collect()),
+            Value::Number(n) => Json::Int(n),
+            Value::Decimal(x) => Json::Number(x),
+            Value::Time(time) => seconds(time),
+            Value::Switch(on) => Json::Bool(on),
+        }
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(text: &str) -> Json {
+        Json::String(text.to_string())
+    }
+}
+
+impl From<bool> for Json {
+    fn from(on: bool) -> Json {
+        Json::Bool(on)
+    }
+}
+
+// Seconds with millisecond precision
+fn seconds(time: Duration) -> Json {
+    Json::Number(time.as_millis() as f64 / 1000.0)
+}
+
+// Milliseconds since the Unix epoch
+fn timestamp(time: SystemTime) -> Json {
+    let millis = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    Json::Int(u64::try_from(millis).unwrap_or(u64::MAX))
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in text.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+// Compact, on one line, so it fits an mIRC variable
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(on) => write!(f, "{on}"),
+            Json::Int(n) => write!(f, "{n}"),
+            Json::Number(x) if x.is_finite() => write!(f, "{x}"),
+            // JSON has no NaN or infinity
+            Json::Number(_) => f.write_str("null"),
+            Json::String(text) => write_string(f, text),
+            Json::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(entries) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn strings(items: &[String]) -> Json {
+    Json::Array(items.iter().map(|s| Json::String(s.clone())).collect())
+}
+
+// The same keys as the current track, read from one session
+fn session(index: usize, session: &SessionSnapshot, state: &MediaState, now: SystemTime) -> Json {
+    let mut entries = vec![("index", Json::Int(index as u64))];
+    for field in Field::ALL.into_iter().filter(|f| f.is_track()) {
+        let value = session
+            .media
+            .as_ref()
+            .map_or(Value::Empty, |media| field.track_value(media));
+        entries.push((field.name(), value.into()));
+    }
+    let timeline = session.timeline;
+    entries.extend([
+        ("status", session.status.map(|s| s.as_str()).into()),
+        (
+            "position",
+            timeline
+                .map(|t| seconds(t.position_at(now, session.speed())))
+                .into(),
+        ),
+        (
+            "duration",
+            timeline.and_then(|t| t.duration).map(seconds).into(),
+        ),
+        (
+            "remaining",
+            timeline
+                .and_then(|t| t.remaining_at(now, session.speed()))
+                .map(seconds)
+                .into(),
+        ),
+        ("shuffle", session.shuffle.into()),
+        ("repeat", session.repeat.map(|r| r.as_str()).into()),
+        ("rate", session.rate.map(Json::Number).into()),
+        (
+            "source",
+            state.names.resolve(&session.app_id).as_str().into(),
+        ),
+        ("appid", session.app_id.as_str().into()),
+        ("current", session.current.into()),
+    ]);
+    Json::Object(entries)
+}
+
+/// Everything the exports know, read in one go. Times are in seconds and extrapolated
+/// to `now`; `updated` and `now` are Unix timestamps in milliseconds.
+pub(crate) fn snapshot(state: &MediaState, now: SystemTime) -> Json {
+    let mut track: Vec<_> = Field::ALL
+        .into_iter()
+        .map(|field| (field.name(), field.read(state, now).into()))
+        .collect();
+    track.push(("thumbnail", state.media.thumbnail_bytes.is_some().into()));
+
+    let policy = &state.selector.policy;
+    Json::Object(vec![
+        ("schema", Json::Int(SCHEMA_VERSION)),
+        ("version", Json::Int(state.version)),
+        ("updated", state.updated.map(timestamp).into()),
+        ("now", timestamp(now)),
+        ("track", Json::Object(track)),
+        (
+            "sessions",
+            Json::Array(
+                state
+                    .sessions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| session(i + 1, s, state, now))
+                    .collect(),
+            ),
+        ),
+        (
+            "policy",
+            Json::Object(vec![
+                ("allow", strings(&policy.allow)),
+                ("deny", strings(&policy.deny)),
+                ("priority", strings(&policy.priority)),
+                ("sticky", policy.sticky.into()),
+                ("pinned", state.selector.pinned().into()),
+            ]),
+        ),
+        (
+            "format",
+            Json::Object(vec![
+                ("genresep", state.format.genre_separator.as_str().into()),
+                ("strip", state.format.strip.into()),
+            ]),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::state::PlaybackStatus;
+    use crate::timeline::Timeline;
+
+    #[test]
+    fn strings_are_escaped() {
+        let json = Json::from("say \"hi\"\\\n\t\u{2}♪");
+        assert_eq!(json.to_string(), r#""say \"hi\"\\\n\t\u0002♪""#);
+    }
+
+    #[test]
+    fn values_map_to_json_types() {
+        let json = Json::Array(vec![
+            Value::Empty.into(),
+            Value::Number(7).into(),
+            Value::Decimal(1.5).into(),
+            Value::Decimal(f64::NAN).into(),
+            Value::Time(Duration::from_millis(83_250)).into(),
+            Value::Switch(false).into(),
+            Value::List(vec!["Rock".to_string(), "Pop".to_string()]).into(),
+        ]);
+        assert_eq!(
+            json.to_string(),
+            r#"[null,7,1.5,null,83.25,false,["Rock","Pop"]]"#
+        );
+        assert_eq!(Json::Object(vec![]).to_string(), "{}");
+    }
+
+    #[test]
+    fn snapshot_covers_track_sessions_and_settings() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let timeline = Timeline {
+            position: Duration::from_secs(10),
+            duration: Some(Duration::from_secs(100)),
+            updated: now,
+        };
+        let mut state = MediaState {
+            app_id: Some("Spotify.exe".to_string()),
+            status: Some(PlaybackStatus::Paused),
+            timeline: Some(timeline),
+            version: 3,
+            updated: Some(now - Duration::from_millis(1500)),
+            sessions: vec![SessionSnapshot {
+                app_id: "Spotify.exe".to_string(),
+                status: Some(PlaybackStatus::Paused),
+                timeline: Some(timeline),
+                current: true,
+                media: Some(crate::state::MediaSnapshot {
+                    title: Some("Song".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        state.media.title = Some("Song".to_string());
+        state.selector.policy.deny = vec!["chrome".to_string()];
+
+        assert_eq!(
+            snapshot(&state, now).to_string(),
+            concat!(
+                r#"{"schema":1,"version":3,"updated":999998500,"now":1000000000,"#,
+                r#""track":{"title":"Song","artist":null,"albumartist":null,"albumtitle":null,"#,
+                r#""genres":null,"playbacktype":null,"subtitle":null,"tracknumber":null,"#,
+                r#""albumtrackcount":null,"status":"paused","position":10,"duration":100,"#,
+                r#""remaining":90,"shuffle":null,"repeat":null,"rate":null,"source":"Spotify","#,
+                r#""appid":"Spotify.exe","thumbnail":false},"#,
+                r#""sessions":[{"index":1,"title":"Song","artist":null,"albumartist":null,"#,
+                r#""albumtitle":null,"genres":null,"playbacktype":null,"subtitle":null,"#,
+                r#""tracknumber":null,"albumtrackcount":null,"status":"paused","position":10,"#,
+                r#""duration":100,"remaining":90,"shuffle":null,"repeat":null,"rate":null,"#,
+                r#""source":"Spotify","appid":"Spotify.exe","current":true}],"#,
+                r#""policy":{"allow":[],"deny":["chrome"],"priority":[],"sticky":false,"pinned":null},"#,
+                r#""format":{"genresep":", ","strip":false}}"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use crate::state::app_id_matches;
+
+// Display names for common players. Keys match like session selectors do, so
+// "spotify" covers both Spotify.exe and the MPRIS "spotify" bus name.
+const BUILTIN: &[(&str, &str)] = &[
+    ("spotify", "Spotify"),
+    ("SpotifyAB.SpotifyMusic_zpdnekdrzrea0!Spotify", "Spotify"),
+    ("chrome", "Chrome"),
+    ("chromium", "Chromium"),
+    ("msedge", "Edge"),
+    ("firefox", "Firefox"),
+    ("308046B0AF4A39CB", "Firefox"),
+    ("opera", "Opera"),
+    ("brave", "Brave"),
+    ("vivaldi", "Vivaldi"),
+    ("foobar2000", "foobar2000"),
+    ("vlc", "VLC"),
+    ("AIMP", "AIMP"),
+    ("MusicBee", "MusicBee"),
+    ("iTunes", "iTunes"),
+    ("AppleInc.AppleMusicWin_nzyj5cx40ttqa!App", "Apple Music"),
+    (
+        "Microsoft.ZuneMusic_8wekyb3d8bbwe!Microsoft.ZuneMusic",
+        "Media Player",
+    ),
+    ("TIDAL", "TIDAL"),
+    ("Discord", "Discord"),
+    ("ms-teams", "Teams"),
+    ("Teams", "Teams"),
+];
+
+/// Maps source app ids to display names: the user's entries first, then the
+/// built-in table, then the app id itself without its extension.
+#[derive(Debug, Default)]
+pub(crate) struct AppNames {
+    custom: Vec<(String, String)>,
+}
+
+impl AppNames {
+    pub(crate) fn resolve(&self, app_id: &str) -> String {
+        self.custom
+            .iter()
+            .map(|(key, name)| (key.as_str(), name.as_str()))
+            .chain(BUILTIN.iter().copied())
+            .find(|(key, _)| app_id_matches(app_id, key))
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| {
+                let stem = app_id.rsplit_once('.').map_or(app_id, |(stem, _)| stem);
+                stem.to_string()
+            })
+    }
+
+    /// Names `key` (matched like a session selector); an empty name removes the entry.
+    pub(crate) fn set(&mut self, key: &str, name: &str) {
+        let (key, name) = (key.trim(), name.trim());
+        self.custom.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        if !name.is_empty() {
+            self.custom.push((key.to_string(), name.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_names_cover_exe_and_bus_names() {
+        let names = AppNames::default();
+        assert_eq!(names.resolve("Spotify.exe"), "Spotify");
+        assert_eq!(names.resolve("spotify"), "Spotify");
+        assert_eq!(names.resolve("chrome"), "Chrome");
+        assert_eq!(names.resolve("firefox.instance_1_84"), "Firefox");
+        assert_eq!(
+            names.resolve("SpotifyAB.SpotifyMusic_zpdnekdrzrea0!Spotify"),
+            "Spotify"
+        );
+    }
+
+    #[test]
+    fn unknown_apps_fall_back_to_their_id() {
+        let names = AppNames::default();
+        assert_eq!(names.resolve("Winamp.exe"), "Winamp");
+        assert_eq!(names.resolve("mpv"), "mpv");
+    }
+
+    #[test]
+    fn custom_names_override_and_can_be_removed() {
+        let mut names = AppNames::default();
+        names.set("chrome", "YouTube");
+        names.set("winamp", " Winamp 5 ");
+        assert_eq!(names.resolve("chrome"), "YouTube");
+        assert_eq!(names.resolve("Winamp.exe"), "Winamp 5");
+
+        names.set("CHROME", "");
+        assert_eq!(names.resolve("chrome"), "Chrome");
+    }
+}
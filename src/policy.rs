@@ -0,0 +1,305 @@
+use std::fmt;
+
+use crate::state::{PlaybackStatus, SessionSnapshot, app_id_matches};
+
+/// Which sessions may become the "current" track, and in what order of preference.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SessionPolicy {
+    pub(crate) allow: Vec<String>,
+    pub(crate) deny: Vec<String>,
+    pub(crate) priority: Vec<String>,
+    pub(crate) sticky: bool,
+}
+
+impl SessionPolicy {
+    // With no rules the backend's own notion of the current session is used as-is
+    pub(crate) fn is_default(&self) -> bool {
+        *self == SessionPolicy::default()
+    }
+
+    pub(crate) fn permits(&self, app_id: &str) -> bool {
+        let listed = |list: &[String]| list.iter().any(|key| app_id_matches(app_id, key));
+        (self.allow.is_empty() || listed(&self.allow)) && !listed(&self.deny)
+    }
+
+    // Position in the priority list; unlisted apps rank after every listed one
+    fn rank(&self, app_id: &str) -> usize {
+        self.priority
+            .iter()
+            .position(|key| app_id_matches(app_id, key))
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Applies a `key=value` spec such as `deny=chrome,teams sticky=on`. Keys that
+    /// are not mentioned keep their value, an empty list clears one, and `reset`
+    /// starts over from the default policy.
+    pub(crate) fn apply(&mut self, spec: &str) -> Result<(), String> {
+        let mut next = self.clone();
+        for word in spec.split_whitespace() {
+            if word.eq_ignore_ascii_case("reset") {
+                next = SessionPolicy::default();
+                continue;
+            }
+            let (key, value) = word
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{word}'"))?;
+            let list = || {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "allow" => next.allow = list(),
+                "deny" => next.deny = list(),
+                "priority" => next.priority = list(),
+                "sticky" => next.sticky = parse_switch(value)?,
+                _ => return Err(format!("unknown policy key '{key}'")),
+            }
+        }
+        *self = next;
+        Ok(())
+    }
+}
+
+pub(crate) fn parse_switch(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "1" | "true" | "yes" => Ok(true),
+        "off" | "0" | "false" | "no" => Ok(false),
+        _ => Err(format!("expected on or off, got '{value}'")),
+    }
+}
+
+impl fmt::Display for SessionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "allow={} deny={} priority={} sticky={}",
+            self.allow.join(","),
+            self.deny.join(","),
+            self.priority.join(","),
+            if self.sticky { "on" } else { "off" }
+        )
+    }
+}
+
+/// The outcome of applying the policy to the session list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Selection {
+    /// Follow whatever the backend considers current.
+    Backend,
+    /// Follow the session with this source app id.
+    Session(String),
+    /// Every session was filtered out.
+    Nothing,
+}
+
+/// Applies a `SessionPolicy` and remembers the previous pick for sticky mode.
+#[derive(Debug, Default)]
+pub(crate) struct SessionSelector {
+    pub(crate) policy: SessionPolicy,
+    // Set by select_session; wins over the policy's ranking while the session exists
+    manual: Option<String>,
+    chosen: Option<String>,
+}
+
+fn has_stopped(session: &SessionSnapshot) -> bool {
+    matches!(
+        session.status,
+        Some(PlaybackStatus::Stopped | PlaybackStatus::Closed)
+    )
+}
+
+impl SessionSelector {
+    pub(crate) fn pin(&mut self, app_id: Option<String>) {
+        self.manual = app_id;
+    }
+
+    pub(crate) fn pinned(&self) -> Option<&str> {
+        self.manual.as_deref()
+    }
+
+    pub(crate) fn select(&mut self, sessions: &[SessionSnapshot]) -> Selection {
+        // The pinned player went away; go back to automatic selection
+        if let Some(manual) = self.manual.as_deref()
+            && !sessions
+                .iter()
+                .any(|s| s.app_id == manual && self.policy.permits(&s.app_id))
+        {
+            self.manual = None;
+        }
+
+        if self.manual.is_none() && self.policy.is_default() {
+            self.chosen = None;
+            return Selection::Backend;
+        }
+
+        let candidates: Vec<(usize, &SessionSnapshot)> = sessions
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| self.policy.permits(&s.app_id))
+            .collect();
+
+        if let Some(manual) = self.manual.as_deref()
+            && let Some((_, s)) = candidates.iter().find(|(_, s)| s.app_id == manual)
+        {
+            return self.choose(Some(s));
+        }
+
+        if self.policy.sticky
+            && let Some(chosen) = self.chosen.as_deref()
+            && let Some((_, s)) = candidates
+                .iter()
+                .find(|(_, s)| s.app_id == chosen && !has_stopped(s))
+        {
+            return self.choose(Some(s));
+        }
+
+        let best = candidates
+            .iter()
+            .min_by_key(|(position, s)| {
+                (
+                    self.policy.rank(&s.app_id),
+                    !s.current,
+                    s.status != Some(PlaybackStatus::Playing),
+                    *position,
+                )
+            })
+            .map(|(_, s)| *s);
+        self.choose(best)
+    }
+
+    fn choose(&mut self, session: Option<&SessionSnapshot>) -> Selection {
+        self.chosen = session.map(|s| s.app_id.clone());
+        match self.chosen {
+            Some(ref app_id) => Selection::Session(app_id.clone()),
+            None => Selection::Nothing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(app_id: &str, status: PlaybackStatus, current: bool) -> SessionSnapshot {
+        SessionSnapshot {
+            app_id: app_id.to_string(),
+            status: Some(status),
+            current,
+            ..Default::default()
+        }
+    }
+
+    fn app(id: &str) -> Selection {
+        Selection::Session(id.to_string())
+    }
+
+    #[test]
+    fn default_policy_defers_to_backend() {
+        let mut selector = SessionSelector::default();
+        let sessions = [session("chrome", PlaybackStatus::Playing, true)];
+        assert_eq!(selector.select(&sessions), Selection::Backend);
+    }
+
+    #[test]
+    fn denylist_skips_the_hijacking_tab() {
+        let mut selector = SessionSelector::default();
+        selector.policy.apply("deny=chrome").unwrap();
+        let sessions = [
+            session("Spotify.exe", PlaybackStatus::Paused, false),
+            session("chrome", PlaybackStatus::Playing, true),
+        ];
+        assert_eq!(selector.select(&sessions), app("Spotify.exe"));
+
+        selector.policy.apply("deny=chrome,spotify").unwrap();
+        assert_eq!(selector.select(&sessions), Selection::Nothing);
+    }
+
+    #[test]
+    fn allowlist_limits_candidates() {
+        let mut selector = SessionSelector::default();
+        selector.policy.apply("allow=foobar2000").unwrap();
+        let sessions = [
+            session("Teams", PlaybackStatus::Playing, true),
+            session("foobar2000.exe", PlaybackStatus::Paused, false),
+        ];
+        assert_eq!(selector.select(&sessions), app("foobar2000.exe"));
+    }
+
+    #[test]
+    fn priority_beats_focus_and_focus_beats_playing() {
+        let mut selector = SessionSelector::default();
+        selector.policy.apply("priority=spotify,vlc").unwrap();
+        let mut sessions = vec![
+            session("chrome", PlaybackStatus::Playing, true),
+            session("vlc", PlaybackStatus::Paused, false),
+            session("Spotify.exe", PlaybackStatus::Paused, false),
+        ];
+        assert_eq!(selector.select(&sessions), app("Spotify.exe"));
+
+        sessions.pop();
+        assert_eq!(selector.select(&sessions), app("vlc"));
+
+        // Without a listed app, the backend's current session wins over a playing one
+        selector.policy.apply("priority= deny=teams").unwrap();
+        let sessions = [
+            session("firefox", PlaybackStatus::Playing, false),
+            session("chrome", PlaybackStatus::Paused, true),
+        ];
+        assert_eq!(selector.select(&sessions), app("chrome"));
+    }
+
+    #[test]
+    fn sticky_keeps_choice_until_it_stops() {
+        let mut selector = SessionSelector::default();
+        selector.policy.apply("sticky=on").unwrap();
+        let mut sessions = vec![
+            session("Spotify.exe", PlaybackStatus::Playing, true),
+            session("chrome", PlaybackStatus::Paused, false),
+        ];
+        assert_eq!(selector.select(&sessions), app("Spotify.exe"));
+
+        // Focus moves to the browser, but Spotify is still going
+        sessions[0].current = false;
+        sessions[1] = session("chrome", PlaybackStatus::Playing, true);
+        assert_eq!(selector.select(&sessions), app("Spotify.exe"));
+
+        sessions[0].status = Some(PlaybackStatus::Stopped);
+        assert_eq!(selector.select(&sessions), app("chrome"));
+    }
+
+    #[test]
+    fn manual_pin_holds_until_session_disappears() {
+        let mut selector = SessionSelector::default();
+        selector.pin(Some("vlc".to_string()));
+        let mut sessions = vec![
+            session("chrome", PlaybackStatus::Playing, true),
+            session("vlc", PlaybackStatus::Stopped, false),
+        ];
+        assert_eq!(selector.select(&sessions), app("vlc"));
+
+        sessions.pop();
+        assert_eq!(selector.select(&sessions), Selection::Backend);
+        assert_eq!(selector.pinned(), None);
+    }
+
+    #[test]
+    fn spec_updates_only_named_keys() {
+        let mut policy = SessionPolicy::default();
+        policy.apply("allow=a,b sticky=on").unwrap();
+        policy.apply("deny=c").unwrap();
+        assert_eq!(policy.to_string(), "allow=a,b deny=c priority= sticky=on");
+
+        assert!(policy.apply("sticky=maybe").is_err());
+        assert!(policy.apply("bogus=1").is_err());
+        assert!(policy.apply("deny").is_err());
+        // A failed spec leaves the policy untouched
+        assert_eq!(policy.to_string(), "allow=a,b deny=c priority= sticky=on");
+
+        policy.apply("reset priority=x").unwrap();
+        assert_eq!(policy.to_string(), "allow= deny= priority=x sticky=off");
+    }
+}
@@ -0,0 +1,607 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use debug_print::debug_eprintln;
+use windows::Foundation::TypedEventHandler;
+use windows::Media::Control::{
+    CurrentSessionChangedEventArgs, GlobalSystemMediaTransportControlsSession,
+    GlobalSystemMediaTransportControlsSessionManager,
+    GlobalSystemMediaTransportControlsSessionPlaybackStatus as GsmtcStatus,
+    MediaPropertiesChangedEventArgs, PlaybackInfoChangedEventArgs, SessionsChangedEventArgs,
+    TimelinePropertiesChangedEventArgs,
+};
+use windows::Media::{MediaPlaybackAutoRepeatMode, MediaPlaybackType};
+use windows::Win32::System::Com::{COINIT_MULTITHREADED, CoInitializeEx, CoUninitialize};
+use windows::core::Interface;
+use windows_future::{AsyncStatus, IAsyncInfo};
+
+use super::session::{SessionHost, SessionTracker};
+use super::{Command, MediaSource, SourceError, SourceEvent};
+use crate::state::{MediaSnapshot, PlaybackStatus, RepeatMode, SessionSnapshot};
+use crate::timeline::Timeline;
+
+// WinRT times count 100ns ticks; DateTime starts at 1601-01-01
+const TICKS_PER_MICRO: i64 = 10;
+const UNIX_EPOCH_TICKS: i64 = 116_444_736_000_000_000;
+
+// How long one WinRT request may run before it is cancelled. Below the exports'
+// command timeout, so a player that hangs is reported as such.
+const ASYNC_TIMEOUT: Duration = Duration::from_secs(3);
+const ASYNC_POLL: Duration = Duration::from_millis(10);
+
+/// Windows Global System Media Transport Controls.
+pub(crate) struct GsmtcSource {
+    host: Option<Host>,
+    events: Option<Sender<SourceEvent>>,
+    session_changed_token: Option<i64>,
+    sessions_changed_token: Option<i64>,
+    sessions: SessionTracker<GlobalSystemMediaTransportControlsSession, (i64, i64, i64)>,
+    // Media properties read per app id; an entry is read again only after its
+    // session reports a property change (see `Host::stale`)
+    metadata: Mutex<HashMap<String, Metadata>>,
+    // Whether start initialized COM, which stop then has to undo
+    com: bool,
+}
+
+#[derive(Default)]
+struct Metadata {
+    media: Option<MediaSnapshot>,
+    // None until somebody asks for the thumbnail; then Some, even if there is none
+    thumbnail: Option<Option<Vec<u8>>>,
+}
+
+// The session manager, plus the app ids whose media properties changed since
+// they were last read. The property handlers run on WinRT threads and only mark
+// their session here; reading happens on the watcher thread.
+struct Host {
+    manager: GlobalSystemMediaTransportControlsSessionManager,
+    stale: Arc<Mutex<HashSet<String>>>,
+    // Told when the manager stops answering; only once, as the watcher restarts
+    // on the first Lost and a second one would restart the new source
+    events: Sender<SourceEvent>,
+    lost: AtomicBool,
+}
+
+impl GsmtcSource {
+    pub(crate) fn new() -> Self {
+        GsmtcSource {
+            host: None,
+            events: None,
+            session_changed_token: None,
+            sessions_changed_token: None,
+            sessions: SessionTracker::new(),
+            metadata: Mutex::new(HashMap::new()),
+            com: false,
+        }
+    }
+
+    // The session's media properties, from the cache unless they changed since
+    // they were read. Failed reads are not kept, so the next refresh retries them.
+    fn media_of(
+        &self,
+        host: &Host,
+        session: &GlobalSystemMediaTransportControlsSession,
+        with_thumbnail: bool,
+    ) -> Option<MediaSnapshot> {
+        let app_id = app_id_of(session);
+        let mut metadata = self.metadata.lock().unwrap();
+        if host.stale.lock().unwrap().remove(&app_id) {
+            metadata.remove(&app_id);
+        }
+        let cached = metadata
+            .get(&app_id)
+            .is_some_and(|entry| !with_thumbnail || entry.thumbnail.is_some());
+        if !cached {
+            let mut media = logged(fetch_session(session, with_thumbnail))?;
+            let thumbnail =
+                with_thumbnail.then(|| media.as_mut().and_then(|m| m.thumbnail_bytes.take()));
+            metadata.insert(app_id.clone(), Metadata { media, thumbnail });
+        }
+        let entry = metadata.get(&app_id)?;
+        let mut media = entry.media.clone()?;
+        if with_thumbnail {
+            media.thumbnail_bytes = entry.thumbnail.clone().flatten();
+        }
+        Some(media)
+    }
+}
+
+impl SessionHost for Host {
+    type Session = GlobalSystemMediaTransportControlsSession;
+    // (MediaPropertiesChanged, PlaybackInfoChanged, TimelinePropertiesChanged)
+    type Token = (i64, i64, i64);
+
+    fn current_session(&self) -> Option<Self::Session> {
+        self.manager.GetCurrentSession().ok()
+    }
+
+    // The list is there even without players, so failing to get it means the
+    // connection to the manager broke, e.g. because its service was restarted
+    fn sessions(&self) -> Vec<Self::Session> {
+        match self.manager.GetSessions() {
+            Ok(list) => list.into_iter().collect(),
+            Err(_err) => {
+                debug_eprintln!("m_nowplaying: session manager failed: {}", _err);
+                if !self.lost.swap(true, Ordering::SeqCst) {
+                    let _ = self.events.send(SourceEvent::Lost);
+                }
+                Vec::new()
+            }
+        }
+    }
+
+    fn subscribe(
+        &self,
+        session: &Self::Session,
+        events: &Sender<SourceEvent>,
+    ) -> Result<(i64, i64, i64), SourceError> {
+        let tx = events.clone();
+        let stale = self.stale.clone();
+        let handler = TypedEventHandler::<
+            GlobalSystemMediaTransportControlsSession,
+            MediaPropertiesChangedEventArgs,
+        >::new(move |session, _args| {
+            if let Some(session) = session.as_ref() {
+                stale.lock().unwrap().insert(app_id_of(session));
+            }
+            let _ = tx.send(SourceEvent::PropertiesChanged);
+            Ok(())
+        });
+        let properties = session.MediaPropertiesChanged(&handler)?;
+
+        let tx = events.clone();
+        let handler = TypedEventHandler::<
+            GlobalSystemMediaTransportControlsSession,
+            PlaybackInfoChangedEventArgs,
+        >::new(move |_s, _args| {
+            let _ = tx.send(SourceEvent::PlaybackChanged);
+            Ok(())
+        });
+        let playback = match session.PlaybackInfoChanged(&handler) {
+            Ok(token) => token,
+            Err(err) => {
+                let _ = session.RemoveMediaPropertiesChanged(properties);
+                return Err(err.into());
+            }
+        };
+
+        let tx = events.clone();
+        let handler = TypedEventHandler::<
+            GlobalSystemMediaTransportControlsSession,
+            TimelinePropertiesChangedEventArgs,
+        >::new(move |_s, _args| {
+            let _ = tx.send(SourceEvent::TimelineChanged);
+            Ok(())
+        });
+        match session.TimelinePropertiesChanged(&handler) {
+            Ok(timeline) => Ok((properties, playback, timeline)),
+            Err(err) => {
+                let _ = session.RemoveMediaPropertiesChanged(properties);
+                let _ = session.RemovePlaybackInfoChanged(playback);
+                Err(err.into())
+            }
+        }
+    }
+
+    fn unsubscribe(
+        &self,
+        session: &Self::Session,
+        (properties, playback, timeline): (i64, i64, i64),
+    ) {
+        let _ = session.RemoveMediaPropertiesChanged(properties);
+        let _ = session.RemovePlaybackInfoChanged(playback);
+        let _ = session.RemoveTimelinePropertiesChanged(timeline);
+    }
+}
+
+impl MediaSource for GsmtcSource {
+    fn name(&self) -> &'static str {
+        "gsmtc"
+    }
+
+    fn start(&mut self, events: Sender<SourceEvent>) -> Result<(), SourceError> {
+        // Initialize COM on the watcher thread
+        self.com = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) }.is_ok();
+
+        let manager = request(
+            GlobalSystemMediaTransportControlsSessionManager::RequestAsync,
+            "the session manager request",
+            |op| op.GetResults(),
+        )?;
+
+        // Register for session changes; the watcher moves the property subscription and re-reads state when they fire
+        let tx = events.clone();
+        let handler = TypedEventHandler::<
+            GlobalSystemMediaTransportControlsSessionManager,
+            CurrentSessionChangedEventArgs,
+        >::new(move |_mgr, _args| {
+            let _ = tx.send(SourceEvent::SessionSwitched);
+            Ok(())
+        });
+        self.session_changed_token = manager.CurrentSessionChanged(&handler).ok();
+
+        // Sessions opening or closing change which sessions we subscribe to
+        let tx = events.clone();
+        let handler = TypedEventHandler::<
+            GlobalSystemMediaTransportControlsSessionManager,
+            SessionsChangedEventArgs,
+        >::new(move |_mgr, _args| {
+            let _ = tx.send(SourceEvent::SessionsChanged);
+            Ok(())
+        });
+        self.sessions_changed_token = manager.SessionsChanged(&handler).ok();
+
+        // Register for media property and playback changes on every session
+        let host = Host {
+            manager,
+            stale: Arc::new(Mutex::new(HashSet::new())),
+            events: events.clone(),
+            lost: AtomicBool::new(false),
+        };
+        self.sessions.follow(&host, &events);
+
+        self.host = Some(host);
+        self.events = Some(events);
+        Ok(())
+    }
+
+    fn on_event(&mut self, event: SourceEvent) {
+        if !matches!(
+            event,
+            SourceEvent::SessionSwitched | SourceEvent::SessionsChanged
+        ) {
+            return;
+        }
+        if let (Some(host), Some(events)) = (self.host.as_ref(), self.events.as_ref()) {
+            self.sessions.follow(host, events);
+        }
+    }
+
+    fn stop(&mut self) {
+        self.events = None;
+        if let Some(host) = self.host.take() {
+            self.sessions.release(&host);
+            if let Some(token) = self.session_changed_token.take() {
+                let _ = host.manager.RemoveCurrentSessionChanged(token);
+            }
+            if let Some(token) = self.sessions_changed_token.take() {
+                let _ = host.manager.RemoveSessionsChanged(token);
+            }
+        }
+        // Nothing marks entries stale while unsubscribed, so they cannot be trusted
+        self.metadata.lock().unwrap().clear();
+        // Runs on the watcher thread, after the last WinRT object is released
+        if std::mem::take(&mut self.com) {
+            unsafe { CoUninitialize() };
+        }
+    }
+
+    fn snapshot(&self) -> Option<MediaSnapshot> {
+        let host = self.host.as_ref()?;
+        let session = host.current_session()?;
+        self.media_of(host, &session, true)
+    }
+
+    fn session_snapshot(&self, app_id: &str) -> Option<MediaSnapshot> {
+        let host = self.host.as_ref()?;
+        let session = session_by_app_id(host, app_id)?;
+        self.media_of(host, &session, true)
+    }
+
+    fn sessions(&self) -> Vec<SessionSnapshot> {
+        let Some(host) = self.host.as_ref() else {
+            return Vec::new();
+        };
+        let current = host.current_session();
+        let sessions = SessionHost::sessions(host);
+        // Forget sessions that have gone away
+        let open: HashSet<String> = sessions.iter().map(app_id_of).collect();
+        self.metadata
+            .lock()
+            .unwrap()
+            .retain(|app_id, _| open.contains(app_id));
+        sessions
+            .iter()
+            .map(|session| {
+                let info = session.GetPlaybackInfo().ok();
+                SessionSnapshot {
+                    app_id: app_id_of(session),
+                    status: info
+                        .as_ref()
+                        .and_then(|info| info.PlaybackStatus().ok())
+                        .and_then(status_from_gsmtc),
+                    timeline: timeline_of(session),
+                    shuffle: info
+                        .as_ref()
+                        .and_then(|info| info.IsShuffleActive().ok())
+                        .and_then(|value| value.Value().ok()),
+                    repeat: info
+                        .as_ref()
+                        .and_then(|info| info.AutoRepeatMode().ok())
+                        .and_then(|value| value.Value().ok())
+                        .and_then(repeat_from_gsmtc),
+                    rate: info
+                        .as_ref()
+                        .and_then(|info| info.PlaybackRate().ok())
+                        .and_then(|value| value.Value().ok()),
+                    current: current.as_ref() == Some(session),
+                    media: self.media_of(host, session, false),
+                }
+            })
+            .collect()
+    }
+
+    fn control(&self, app_id: Option<&str>, command: Command) -> Result<(), SourceError> {
+        let host = self
+            .host
+            .as_ref()
+            .ok_or_else(|| SourceError::new("not started"))?;
+        let session = match app_id {
+            Some(app_id) => session_by_app_id(host, app_id),
+            None => host.current_session(),
+        }
+        .ok_or_else(|| SourceError::new("no media session"))?;
+
+        let op = match command {
+            Command::Play => session.TryPlayAsync()?,
+            Command::Pause => session.TryPauseAsync()?,
+            Command::Toggle => session.TryTogglePlayPauseAsync()?,
+            Command::Next => session.TrySkipNextAsync()?,
+            Command::Previous => session.TrySkipPreviousAsync()?,
+            Command::Stop => session.TryStopAsync()?,
+            Command::Seek(to) => {
+                // Positions are measured on the session's timeline, which may not start at zero
+                let start = session
+                    .GetTimelineProperties()
+                    .and_then(|props| props.StartTime())
+                    .map(|time| time.Duration)
+                    .unwrap_or(0);
+                let position = i64::try_from(to.as_micros())
+                    .ok()
+                    .and_then(|micros| micros.checked_mul(TICKS_PER_MICRO))
+                    .and_then(|offset| start.checked_add(offset))
+                    .ok_or_else(|| SourceError::new("position out of range"))?;
+                session.TryChangePlaybackPositionAsync(position)?
+            }
+            Command::Shuffle(on) => session.TryChangeShuffleActiveAsync(on)?,
+            Command::Repeat(mode) => session.TryChangeAutoRepeatModeAsync(match mode {
+                RepeatMode::None => MediaPlaybackAutoRepeatMode::None,
+                RepeatMode::Track => MediaPlaybackAutoRepeatMode::Track,
+                RepeatMode::List => MediaPlaybackAutoRepeatMode::List,
+            })?,
+            Command::Rate(rate) => session.TryChangePlaybackRateAsync(rate)?,
+        };
+        finish(&op, "the command")?;
+        if op.GetResults()? {
+            Ok(())
+        } else {
+            Err(SourceError::new("the player declined the command"))
+        }
+    }
+}
+
+/// Waits for a WinRT async operation to complete, for at most `ASYNC_TIMEOUT`,
+/// and cancels it when it takes longer. `what` names the request in errors.
+fn finish<T: Interface>(op: &T, what: &str) -> Result<(), SourceError> {
+    let info: IAsyncInfo = op.cast()?;
+    let deadline = Instant::now() + ASYNC_TIMEOUT;
+    loop {
+        match info.Status()? {
+            AsyncStatus::Completed => return Ok(()),
+            AsyncStatus::Canceled => {
+                return Err(SourceError::new(format!("{what} was cancelled")));
+            }
+            AsyncStatus::Error => {
+                let err = SourceError::from(windows::core::Error::from(info.ErrorCode()?));
+                return Err(SourceError::new(format!("{what} failed: {err}")));
+            }
+            _ if Instant::now() >= deadline => {
+                let _ = info.Cancel();
+                return Err(SourceError::new(format!(
+                    "{what} did not complete within {} seconds",
+                    ASYNC_TIMEOUT.as_secs()
+                )));
+            }
+            _ => thread::sleep(ASYNC_POLL),
+        }
+    }
+}
+
+// For reads that fall back to "nothing known": logs why, in debug builds
+fn logged<T>(result: Result<T, SourceError>) -> Option<T> {
+    result
+        .inspect_err(|_err| {
+            debug_eprintln!("m_nowplaying: {}", _err);
+        })
+        .ok()
+}
+
+fn session_by_app_id(
+    host: &Host,
+    app_id: &str,
+) -> Option<GlobalSystemMediaTransportControlsSession> {
+    SessionHost::sessions(host)
+        .into_iter()
+        .find(|session| app_id_of(session) == app_id)
+}
+
+fn app_id_of(session: &GlobalSystemMediaTransportControlsSession) -> String {
+    session
+        .SourceAppUserModelId()
+        .map(|id| id.to_string())
+        .unwrap_or_default()
+}
+
+fn status_from_gsmtc(status: GsmtcStatus) -> Option<PlaybackStatus> {
+    match status {
+        GsmtcStatus::Closed => Some(PlaybackStatus::Closed),
+        GsmtcStatus::Opened => Some(PlaybackStatus::Opened),
+        GsmtcStatus::Changing => Some(PlaybackStatus::Changing),
+        GsmtcStatus::Stopped => Some(PlaybackStatus::Stopped),
+        GsmtcStatus::Playing => Some(PlaybackStatus::Playing),
+        GsmtcStatus::Paused => Some(PlaybackStatus::Paused),
+        _ => None,
+    }
+}
+
+fn repeat_from_gsmtc(mode: MediaPlaybackAutoRepeatMode) -> Option<RepeatMode> {
+    match mode {
+        MediaPlaybackAutoRepeatMode::None => Some(RepeatMode::None),
+        MediaPlaybackAutoRepeatMode::Track => Some(RepeatMode::Track),
+        MediaPlaybackAutoRepeatMode::List => Some(RepeatMode::List),
+        _ => None,
+    }
+}
+
+fn ticks(ticks: i64) -> Duration {
+    Duration::from_micros(u64::try_from(ticks / TICKS_PER_MICRO).unwrap_or(0))
+}
+
+fn timeline_of(session: &GlobalSystemMediaTransportControlsSession) -> Option<Timeline> {
+    let props = session.GetTimelineProperties().ok()?;
+    let start = props.StartTime().ok()?.Duration;
+    let end = props.EndTime().ok()?.Duration;
+    let position = props.Position().ok()?.Duration;
+    // Some apps never set the update time; treat their position as fresh
+    let updated = match props.LastUpdatedTime() {
+        Ok(time) if time.UniversalTime > UNIX_EPOCH_TICKS => {
+            SystemTime::UNIX_EPOCH + ticks(time.UniversalTime - UNIX_EPOCH_TICKS)
+        }
+        _ => SystemTime::now(),
+    };
+    Some(Timeline {
+        position: ticks(position - start),
+        duration: (end > start).then(|| ticks(end - start)),
+        updated,
+    })
+}
+
+fn playback_type_to_string(pt: MediaPlaybackType) -> &'static str {
+    match pt {
+        MediaPlaybackType::Music => "Music",
+        MediaPlaybackType::Video => "Video",
+        MediaPlaybackType::Image => "Image",
+        _ => "Unknown",
+    }
+}
+
+// Reads a session's media properties; the thumbnail is only fetched when asked for
+fn fetch_session(
+    session: &GlobalSystemMediaTransportControlsSession,
+    with_thumbnail: bool,
+) -> Result<Option<MediaSnapshot>, SourceError> {
+    let props = request(
+        || session.TryGetMediaPropertiesAsync(),
+        "the media properties request",
+        |op| op.GetResults(),
+    )?;
+
+    let title = props.Title().unwrap_or_default().to_string();
+    let artist = props.Artist().unwrap_or_default().to_string();
+    let album_title = props.AlbumTitle().ok().map(|s| s.to_string());
+    let album_artist = props.AlbumArtist().ok().map(|s| s.to_string());
+    let subtitle = props.Subtitle().ok().map(|s| s.to_string());
+    let track_number = props.TrackNumber().ok().map(|v| v as u32); // API returns i32
+    let album_track_count = props.AlbumTrackCount().ok().map(|v| v as u32);
+
+    // PlaybackType is an IReference<MediaPlaybackType>; use Value() accessor
+    let playback_type = props
+        .PlaybackType()
+        .ok()
+        .and_then(|iref| iref.Value().ok())
+        .map(|p| playback_type_to_string(p).to_string());
+
+    // Thumbnail: read bytes into memory (best-effort)
+    let thumbnail_bytes = if with_thumbnail {
+        props
+            .Thumbnail()
+            .ok()
+            .and_then(|thr| logged(read_thumbnail_bytes(&thr)))
+            .flatten()
+    } else {
+        None
+    };
+
+    // Genres
+    let genres = match props.Genres() {
+        Ok(gv) => {
+            let mut v = Vec::new();
+            if let Ok(sz) = gv.Size() {
+                let mut i = 0;
+                while i < sz {
+                    if let Ok(item) = gv.GetAt(i) {
+                        v.push(item.to_string());
+                    }
+                    i += 1;
+                }
+            }
+            if v.is_empty() { None } else { Some(v) }
+        }
+        Err(_) => None,
+    };
+
+    // Treat empty metadata as None so transient states don't trigger wakeups
+    if title.trim().is_empty() && artist.trim().is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(MediaSnapshot {
+        title: Some(title),
+        artist: Some(artist),
+        album_title,
+        album_artist,
+        genres,
+        subtitle,
+        track_number,
+        album_track_count,
+        playback_type,
+        thumbnail_bytes,
+    }))
+}
+
+// Starts an async request with `start` and waits for its results
+fn request<O, T>(
+    start: impl FnOnce() -> windows::core::Result<O>,
+    what: &str,
+    results: impl FnOnce(&O) -> windows::core::Result<T>,
+) -> Result<T, SourceError>
+where
+    O: Interface,
+{
+    let op = start()?;
+    finish(&op, what)?;
+    Ok(results(&op)?)
+}
+
+// Read the thumbnail RandomAccessStream into a Vec<u8>; `None` when there is no image
+fn read_thumbnail_bytes(
+    thr: &windows::Storage::Streams::IRandomAccessStreamReference,
+) -> Result<Option<Vec<u8>>, SourceError> {
+    use windows::Storage::Streams::{Buffer, DataReader, InputStreamOptions};
+
+    let stream = request(
+        || thr.OpenReadAsync(),
+        "opening the thumbnail",
+        |op| op.GetResults(),
+    )?;
+    // Determine size (cap to a reasonable limit, e.g., 10MB)
+    let size = stream.Size().ok().unwrap_or(0);
+    let cap = std::cmp::min(size as u32, 10_000_000);
+    if cap == 0 {
+        return Ok(None);
+    }
+    let buf = Buffer::Create(cap)?;
+    let filled = request(
+        || stream.ReadAsync(&buf, cap, InputStreamOptions::None),
+        "reading the thumbnail",
+        |op| op.GetResults(),
+    )?;
+    let reader = DataReader::FromBuffer(&filled)?;
+    let mut data = vec![0u8; filled.Length()? as usize];
+    reader.ReadBytes(&mut data)?;
+    Ok(Some(data))
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use crate::state::{MediaSnapshot, RepeatMode, SessionSnapshot};
+
+#[cfg(windows)]
+mod gsmtc;
+#[cfg(target_os = "linux")]
+mod mpris;
+#[cfg(test)]
+pub(crate) mod scripted;
+mod session;
+
+#[cfg(windows)]
+pub(crate) use gsmtc::GsmtcSource;
+#[cfg(target_os = "linux")]
+pub(crate) use mpris::MprisSource;
+
+/// Change notifications a source pushes to the watcher. They carry no payload;
+/// the watcher reacts by asking the source for a fresh snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SourceEvent {
+    SessionSwitched,
+    SessionsChanged,
+    PropertiesChanged,
+    PlaybackChanged,
+    TimelineChanged,
+    /// The backend went away; the watcher stops the source and starts it again.
+    /// MPRIS sends it when the bus connection closes, GSMTC when the session
+    /// manager stops answering.
+    Lost,
+    /// Not sent by sources: the DLL side changed the session policy.
+    PolicyChanged,
+    /// Not sent by sources: the DLL side queued a transport command.
+    CommandQueued,
+    /// Not sent by sources: the DLL side wants the watcher to stop.
+    Shutdown,
+    /// Not sent by sources: a query wants the player read even though nobody listens.
+    Fetch,
+}
+
+/// Transport commands scripts can send to a session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Command {
+    Play,
+    Pause,
+    Toggle,
+    Next,
+    Previous,
+    Stop,
+    /// Jump to this offset from the start of the track.
+    Seek(Duration),
+    Shuffle(bool),
+    Repeat(RepeatMode),
+    /// Playback speed, 1.0 being normal.
+    Rate(f64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SourceError(String);
+
+impl SourceError {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        SourceError(message.into())
+    }
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(windows)]
+impl From<windows::core::Error> for SourceError {
+    fn from(err: windows::core::Error) -> Self {
+        SourceError(format!("{:#010x}: {}", err.code().0, err.message()))
+    }
+}
+
+/// A backend that knows where the currently playing media lives.
+///
+/// `start` is called on the watcher thread, which then owns the source for its
+/// whole lifetime; `snapshot` is only ever called from that thread.
+pub(crate) trait MediaSource: Send + 'static {
+    /// Short backend name for diagnostics, e.g. `mpris`.
+    fn name(&self) -> &'static str;
+
+    /// Connects to the backend and starts forwarding change notifications to `events`.
+    fn start(&mut self, events: Sender<SourceEvent>) -> Result<(), SourceError>;
+
+    /// Lets the source react to one of its own notifications before the watcher
+    /// takes a snapshot, e.g. to move subscriptions to a newly focused session.
+    fn on_event(&mut self, _event: SourceEvent) {}
+
+    /// Stops forwarding notifications and releases backend resources. Also called
+    /// after `start` failed, to undo whatever it got to set up.
+    fn stop(&mut self);
+
+    /// Reads the current metadata, or `None` if nothing is playing.
+    fn snapshot(&self) -> Option<MediaSnapshot>;
+
+    /// Reads the metadata of a specific session, identified by source app id.
+    fn session_snapshot(&self, app_id: &str) -> Option<MediaSnapshot>;
+
+    /// Lists every session the backend knows about, in the backend's order.
+    fn sessions(&self) -> Vec<SessionSnapshot>;
+
+    /// Sends `command` to the session with source app id `app_id`, or to the
+    /// backend's current session when `None`.
+    fn control(&self, app_id: Option<&str>, command: Command) -> Result<(), SourceError>;
+}
+
+/// The backend the watcher uses on this platform.
+#[cfg(windows)]
+pub(crate) fn platform_source() -> GsmtcSource {
+    GsmtcSource::new()
+}
+
+/// The backend the watcher uses on this platform.
+#[cfg(target_os = "linux")]
+pub(crate) fn platform_source() -> MprisSource {
+    MprisSource::session()
+}
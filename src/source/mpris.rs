@@ -0,0 +1,760 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use zbus::blocking::fdo::DBusProxy;
+use zbus::blocking::{Connection, MessageIterator, connection};
+use zbus::message::Type as MessageType;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+use zbus::{MatchRule, Message};
+
+use super::{Command, MediaSource, SourceError, SourceEvent};
+use crate::state::{MediaSnapshot, PlaybackStatus, RepeatMode, SessionSnapshot};
+use crate::timeline::Timeline;
+
+const PLAYER_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PLAYER_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_IFACE: &str = "org.mpris.MediaPlayer2.Player";
+const PROPERTIES_IFACE: &str = "org.freedesktop.DBus.Properties";
+
+// Cap thumbnail reads the same way the GSMTC backend does
+const MAX_THUMBNAIL_BYTES: u64 = 10_000_000;
+
+// How long a player may take to answer a call, like GSMTC's async timeout; a hung
+// player would otherwise stall the watcher for good
+const METHOD_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// MPRIS players on the D-Bus session bus (or a bus at an explicit address).
+#[derive(Default)]
+pub(crate) struct MprisSource {
+    address: Option<String>,
+    connection: Option<Connection>,
+    // Set before stop closes the connection, so the listener thread knows the end is expected
+    stopping: Arc<AtomicBool>,
+}
+
+impl MprisSource {
+    pub(crate) fn session() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn with_address(address: impl Into<String>) -> Self {
+        MprisSource {
+            address: Some(address.into()),
+            ..Self::default()
+        }
+    }
+
+    fn connect(&self) -> zbus::Result<Connection> {
+        let builder = match self.address {
+            Some(ref address) => connection::Builder::address(address.as_str())?,
+            None => connection::Builder::session()?,
+        };
+        builder.method_timeout(METHOD_TIMEOUT).build()
+    }
+}
+
+impl From<zbus::Error> for SourceError {
+    fn from(err: zbus::Error) -> Self {
+        SourceError::new(err.to_string())
+    }
+}
+
+impl From<zbus::fdo::Error> for SourceError {
+    fn from(err: zbus::fdo::Error) -> Self {
+        SourceError::new(err.to_string())
+    }
+}
+
+impl MediaSource for MprisSource {
+    fn name(&self) -> &'static str {
+        "mpris"
+    }
+
+    fn start(&mut self, events: Sender<SourceEvent>) -> Result<(), SourceError> {
+        let conn = self.connect()?;
+
+        // Property changes on any player's Player interface
+        let properties = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .interface(PROPERTIES_IFACE)?
+            .member("PropertiesChanged")?
+            .path(PLAYER_PATH)?
+            .arg(0, PLAYER_IFACE)?
+            .build();
+        // Position jumps; MPRIS does not signal ordinary progress
+        let seeked = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .interface(PLAYER_IFACE)?
+            .member("Seeked")?
+            .path(PLAYER_PATH)?
+            .build();
+        // Players appearing on or leaving the bus
+        let owners = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .interface("org.freedesktop.DBus")?
+            .member("NameOwnerChanged")?
+            .arg0ns("org.mpris.MediaPlayer2")?
+            .build();
+
+        let messages = MessageIterator::from(&conn);
+        let dbus = DBusProxy::new(&conn)?;
+        dbus.add_match_rule(properties)?;
+        dbus.add_match_rule(seeked)?;
+        dbus.add_match_rule(owners)?;
+
+        let stopping = Arc::new(AtomicBool::new(false));
+        self.stopping = stopping.clone();
+        thread::spawn(move || {
+            for msg in messages {
+                let Ok(msg) = msg else { break };
+                let Some(event) = classify(&msg) else {
+                    continue;
+                };
+                if events.send(event).is_err() {
+                    return;
+                }
+            }
+            // The bus went away under us
+            if !stopping.load(Ordering::SeqCst) {
+                let _ = events.send(SourceEvent::Lost);
+            }
+        });
+
+        self.connection = Some(conn);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        // Closing the socket ends the message iterator, which ends the listener thread
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Some(conn) = self.connection.take() {
+            let _ = conn.close();
+        }
+    }
+
+    fn snapshot(&self) -> Option<MediaSnapshot> {
+        let conn = self.connection.as_ref()?;
+        player_snapshot(conn, &pick_player(conn)?)
+    }
+
+    fn session_snapshot(&self, app_id: &str) -> Option<MediaSnapshot> {
+        let conn = self.connection.as_ref()?;
+        player_snapshot(conn, &format!("{PLAYER_PREFIX}{app_id}"))
+    }
+
+    fn sessions(&self) -> Vec<SessionSnapshot> {
+        let Some(conn) = self.connection.as_ref() else {
+            return Vec::new();
+        };
+        let current = pick_player(conn);
+        player_names(conn)
+            .into_iter()
+            .map(|name| {
+                let props = player_properties(conn, &name).unwrap_or_default();
+                let metadata = metadata_of(&props);
+                SessionSnapshot {
+                    app_id: name[PLAYER_PREFIX.len()..].to_string(),
+                    status: status_of(&props),
+                    timeline: timeline_of(&props, metadata.as_ref()),
+                    shuffle: props.get("Shuffle").and_then(|v| as_bool(v)),
+                    repeat: repeat_of(&props),
+                    rate: props.get("Rate").and_then(|v| as_f64(v)),
+                    current: current.as_deref() == Some(name.as_str()),
+                    media: metadata.and_then(|m| snapshot_from_metadata(&m)),
+                }
+            })
+            .collect()
+    }
+
+    fn control(&self, app_id: Option<&str>, command: Command) -> Result<(), SourceError> {
+        let conn = self
+            .connection
+            .as_ref()
+            .ok_or_else(|| SourceError::new("not connected"))?;
+        let name = match app_id {
+            Some(app_id) => format!("{PLAYER_PREFIX}{app_id}"),
+            None => pick_player(conn).ok_or_else(|| SourceError::new("no media session"))?,
+        };
+        let call = |method: &str| {
+            conn.call_method(
+                Some(name.as_str()),
+                PLAYER_PATH,
+                Some(PLAYER_IFACE),
+                method,
+                &(),
+            )
+        };
+        let set = |property: &str, value: Value<'_>| {
+            conn.call_method(
+                Some(name.as_str()),
+                PLAYER_PATH,
+                Some(PROPERTIES_IFACE),
+                "Set",
+                &(PLAYER_IFACE, property, value),
+            )
+        };
+        match command {
+            Command::Play => call("Play")?,
+            Command::Pause => call("Pause")?,
+            Command::Toggle => call("PlayPause")?,
+            Command::Next => call("Next")?,
+            Command::Previous => call("Previous")?,
+            Command::Stop => call("Stop")?,
+            Command::Seek(to) => {
+                // SetPosition is ignored unless it names the track that is playing
+                let props = player_properties(conn, &name)?;
+                let track = metadata_of(&props)
+                    .and_then(|m| m.get("mpris:trackid").and_then(|v| as_object_path(v)))
+                    .ok_or_else(|| SourceError::new("player did not report a track id"))?;
+                let position = i64::try_from(to.as_micros())
+                    .map_err(|_| SourceError::new("position out of range"))?;
+                conn.call_method(
+                    Some(name.as_str()),
+                    PLAYER_PATH,
+                    Some(PLAYER_IFACE),
+                    "SetPosition",
+                    &(track, position),
+                )?
+            }
+            Command::Shuffle(on) => set("Shuffle", Value::from(on))?,
+            Command::Repeat(mode) => {
+                let status = match mode {
+                    RepeatMode::None => "None",
+                    RepeatMode::Track => "Track",
+                    RepeatMode::List => "Playlist",
+                };
+                set("LoopStatus", Value::from(status))?
+            }
+            Command::Rate(rate) => set("Rate", Value::from(rate))?,
+        };
+        Ok(())
+    }
+}
+
+// Full metadata for one player, including its artwork
+fn player_snapshot(conn: &Connection, name: &str) -> Option<MediaSnapshot> {
+    let props = player_properties(conn, name).ok()?;
+    let metadata = metadata_of(&props)?;
+    let mut snapshot = snapshot_from_metadata(&metadata)?;
+    snapshot.thumbnail_bytes = metadata
+        .get("mpris:artUrl")
+        .and_then(|v| as_string(v))
+        .and_then(|url| read_art_url(&url));
+    Some(snapshot)
+}
+
+fn classify(msg: &Message) -> Option<SourceEvent> {
+    let header = msg.header();
+    if header.message_type() != MessageType::Signal {
+        return None;
+    }
+    match header.member()?.as_str() {
+        "PropertiesChanged" => Some(SourceEvent::PropertiesChanged),
+        "Seeked" => Some(SourceEvent::TimelineChanged),
+        "NameOwnerChanged" => Some(SourceEvent::SessionSwitched),
+        _ => None,
+    }
+}
+
+fn player_names(conn: &Connection) -> Vec<String> {
+    let Ok(dbus) = DBusProxy::new(conn) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = dbus
+        .list_names()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|n| n.to_string())
+        .filter(|n| n.starts_with(PLAYER_PREFIX))
+        .collect();
+    names.sort();
+    names
+}
+
+// Prefer a player that is actually playing; otherwise take the first one on the bus
+fn pick_player(conn: &Connection) -> Option<String> {
+    let names = player_names(conn);
+    names
+        .iter()
+        .find(|name| {
+            player_properties(conn, name)
+                .ok()
+                .and_then(|props| status_of(&props))
+                == Some(PlaybackStatus::Playing)
+        })
+        .or(names.first())
+        .cloned()
+}
+
+fn status_of(props: &HashMap<String, OwnedValue>) -> Option<PlaybackStatus> {
+    match as_string(props.get("PlaybackStatus")?)?.as_str() {
+        "Playing" => Some(PlaybackStatus::Playing),
+        "Paused" => Some(PlaybackStatus::Paused),
+        "Stopped" => Some(PlaybackStatus::Stopped),
+        _ => None,
+    }
+}
+
+fn repeat_of(props: &HashMap<String, OwnedValue>) -> Option<RepeatMode> {
+    match as_string(props.get("LoopStatus")?)?.as_str() {
+        "None" => Some(RepeatMode::None),
+        "Track" => Some(RepeatMode::Track),
+        "Playlist" => Some(RepeatMode::List),
+        _ => None,
+    }
+}
+
+fn metadata_of(props: &HashMap<String, OwnedValue>) -> Option<HashMap<String, OwnedValue>> {
+    HashMap::try_from(props.get("Metadata")?.try_clone().ok()?).ok()
+}
+
+// Position is read on demand, so it is sampled now; lengths are in microseconds
+fn timeline_of(
+    props: &HashMap<String, OwnedValue>,
+    metadata: Option<&HashMap<String, OwnedValue>>,
+) -> Option<Timeline> {
+    let micros = |value: &Value<'_>| as_i64(value).and_then(|n| u64::try_from(n).ok());
+    let position = micros(props.get("Position")?)?;
+    let duration = metadata
+        .and_then(|m| m.get("mpris:length"))
+        .and_then(|v| micros(v))
+        .filter(|&n| n > 0);
+    Some(Timeline {
+        position: Duration::from_micros(position),
+        duration: duration.map(Duration::from_micros),
+        updated: SystemTime::now(),
+    })
+}
+
+fn player_properties(conn: &Connection, name: &str) -> zbus::Result<HashMap<String, OwnedValue>> {
+    let reply = conn.call_method(
+        Some(name),
+        PLAYER_PATH,
+        Some(PROPERTIES_IFACE),
+        "GetAll",
+        &(PLAYER_IFACE,),
+    )?;
+    reply.body().deserialize()
+}
+
+fn as_string(value: &Value<'_>) -> Option<String> {
+    match value {
+        Value::Str(s) => Some(s.to_string()),
+        Value::Value(inner) => as_string(inner),
+        _ => None,
+    }
+}
+
+// mpris:trackid is an object path, though some players send it as a string
+fn as_object_path(value: &Value<'_>) -> Option<OwnedObjectPath> {
+    match value {
+        Value::ObjectPath(path) => Some(path.clone().into()),
+        Value::Str(s) => ObjectPath::try_from(s.as_str()).ok().map(Into::into),
+        Value::Value(inner) => as_object_path(inner),
+        _ => None,
+    }
+}
+
+// xesam list fields are `as`, but some players send a plain string
+fn as_strings(value: &Value<'_>) -> Option<Vec<String>> {
+    match value {
+        Value::Array(items) => Some(items.iter().filter_map(as_string).collect()),
+        Value::Value(inner) => as_strings(inner),
+        other => as_string(other).map(|s| vec![s]),
+    }
+}
+
+fn as_u32(value: &Value<'_>) -> Option<u32> {
+    match *value {
+        Value::I32(n) => u32::try_from(n).ok(),
+        Value::U32(n) => Some(n),
+        Value::I64(n) => u32::try_from(n).ok(),
+        Value::U64(n) => u32::try_from(n).ok(),
+        Value::Value(ref inner) => as_u32(inner),
+        _ => None,
+    }
+}
+
+fn as_bool(value: &Value<'_>) -> Option<bool> {
+    match *value {
+        Value::Bool(b) => Some(b),
+        Value::Value(ref inner) => as_bool(inner),
+        _ => None,
+    }
+}
+
+fn as_f64(value: &Value<'_>) -> Option<f64> {
+    match *value {
+        Value::F64(n) => Some(n),
+        Value::Value(ref inner) => as_f64(inner),
+        _ => None,
+    }
+}
+
+fn as_i64(value: &Value<'_>) -> Option<i64> {
+    match *value {
+        Value::I32(n) => Some(n.into()),
+        Value::U32(n) => Some(n.into()),
+        Value::I64(n) => Some(n),
+        Value::U64(n) => i64::try_from(n).ok(),
+        Value::Value(ref inner) => as_i64(inner),
+        _ => None,
+    }
+}
+
+fn snapshot_from_metadata(metadata: &HashMap<String, OwnedValue>) -> Option<MediaSnapshot> {
+    let text = |key: &str| metadata.get(key).and_then(|v| as_string(v));
+    let list = |key: &str| {
+        metadata
+            .get(key)
+            .and_then(|v| as_strings(v))
+            .filter(|v| !v.is_empty())
+    };
+
+    let title = text("xesam:title").unwrap_or_default();
+    let artist = list("xesam:artist")
+        .map(|a| a.join(", "))
+        .unwrap_or_default();
+
+    // Treat empty metadata as None so transient states don't trigger wakeups
+    if title.trim().is_empty() && artist.trim().is_empty() {
+        return None;
+    }
+
+    Some(MediaSnapshot {
+        title: Some(title),
+        artist: Some(artist),
+        album_title: text("xesam:album"),
+        album_artist: list("xesam:albumArtist").map(|a| a.join(", ")),
+        genres: list("xesam:genre"),
+        subtitle: None,
+        track_number: metadata.get("xesam:trackNumber").and_then(|v| as_u32(v)),
+        album_track_count: None,
+        playback_type: None,
+        // Only the current player's artwork is loaded, see `MprisSource::snapshot`
+        thumbnail_bytes: None,
+    })
+}
+
+// Only local artwork is loaded; remote URLs would mean network I/O on the watcher thread
+fn read_art_url(url: &str) -> Option<Vec<u8>> {
+    let path = percent_decode(url.strip_prefix("file://")?)?;
+    let len = std::fs::metadata(&path).ok()?.len();
+    if len == 0 || len > MAX_THUMBNAIL_BYTES {
+        return None;
+    }
+    std::fs::read(path).ok()
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader};
+    use std::process::{self, Child, Stdio};
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+
+    fn metadata(entries: Vec<(&str, Value<'static>)>) -> HashMap<String, OwnedValue> {
+        entries
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), OwnedValue::try_from(v).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn metadata_maps_onto_snapshot_fields() {
+        let meta = metadata(vec![
+            ("xesam:title", Value::from("Song")),
+            ("xesam:artist", Value::from(vec!["A", "B"])),
+            ("xesam:album", Value::from("Album")),
+            ("xesam:albumArtist", Value::from(vec!["A"])),
+            ("xesam:trackNumber", Value::from(7i32)),
+            ("xesam:genre", Value::from(vec!["Rock", "Pop"])),
+            (
+                "mpris:artUrl",
+                Value::from("https://example.invalid/art.png"),
+            ),
+        ]);
+        let snap = snapshot_from_metadata(&meta).unwrap();
+        assert_eq!(snap.title.as_deref(), Some("Song"));
+        assert_eq!(snap.artist.as_deref(), Some("A, B"));
+        assert_eq!(snap.album_title.as_deref(), Some("Album"));
+        assert_eq!(snap.album_artist.as_deref(), Some("A"));
+        assert_eq!(snap.track_number, Some(7));
+        assert_eq!(
+            snap.genres,
+            Some(vec!["Rock".to_string(), "Pop".to_string()])
+        );
+    }
+
+    #[test]
+    fn timeline_comes_from_position_and_length() {
+        let props = metadata(vec![("Position", Value::from(83_500_000i64))]);
+        let meta = metadata(vec![("mpris:length", Value::from(200_000_000u64))]);
+        let timeline = timeline_of(&props, Some(&meta)).unwrap();
+        assert_eq!(timeline.position, Duration::from_millis(83_500));
+        assert_eq!(timeline.duration, Some(Duration::from_secs(200)));
+
+        // Unknown or zero lengths mean a stream
+        let timeline = timeline_of(&props, None).unwrap();
+        assert_eq!(timeline.duration, None);
+        assert_eq!(timeline_of(&HashMap::new(), Some(&meta)), None);
+    }
+
+    #[test]
+    fn empty_metadata_is_no_media() {
+        let meta = metadata(vec![("xesam:title", Value::from(" "))]);
+        assert_eq!(snapshot_from_metadata(&meta), None);
+        assert_eq!(snapshot_from_metadata(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn local_art_url_is_read() {
+        let path = std::env::temp_dir().join(format!("m np art {}.png", std::process::id()));
+        std::fs::write(&path, b"png").unwrap();
+        let url = format!("file://{}", path.display()).replace(' ', "%20");
+        assert_eq!(read_art_url(&url), Some(b"png".to_vec()));
+        let _ = std::fs::remove_file(path);
+    }
+
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    // A throwaway session bus; None when dbus-daemon isn't installed
+    fn private_bus() -> Option<PrivateBus> {
+        let mut daemon = process::Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some(PrivateBus {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+
+    struct FakePlayer {
+        title: Arc<Mutex<String>>,
+        calls: Arc<Mutex<Vec<String>>>,
+        shuffle: bool,
+    }
+
+    #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl FakePlayer {
+        fn next(&self) {
+            self.calls.lock().unwrap().push("Next".to_string());
+        }
+
+        fn set_position(&self, track: OwnedObjectPath, position: i64) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("SetPosition {} {}", track.as_str(), position));
+        }
+
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            "Playing".to_string()
+        }
+
+        #[zbus(property)]
+        fn shuffle(&self) -> bool {
+            self.shuffle
+        }
+
+        #[zbus(property)]
+        fn set_shuffle(&mut self, shuffle: bool) {
+            self.shuffle = shuffle;
+        }
+
+        #[zbus(property)]
+        fn loop_status(&self) -> String {
+            "Playlist".to_string()
+        }
+
+        #[zbus(property)]
+        fn rate(&self) -> f64 {
+            1.0
+        }
+
+        #[zbus(property)]
+        fn position(&self) -> i64 {
+            12_000_000
+        }
+
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<String, OwnedValue> {
+            metadata(vec![
+                (
+                    "xesam:title",
+                    Value::from(self.title.lock().unwrap().clone()),
+                ),
+                ("xesam:artist", Value::from(vec!["Fake Artist"])),
+                ("xesam:trackNumber", Value::from(3i32)),
+                ("mpris:length", Value::from(180_000_000i64)),
+                (
+                    "mpris:trackid",
+                    Value::from(ObjectPath::from_static_str_unchecked("/fake/track/1")),
+                ),
+            ])
+        }
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon; run with --include-ignored"]
+    fn reads_and_follows_a_player_on_a_private_bus() {
+        let bus = private_bus().expect("dbus-daemon is not installed");
+
+        let title = Arc::new(Mutex::new("First".to_string()));
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let player = connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name("org.mpris.MediaPlayer2.fake")
+            .unwrap()
+            .serve_at(
+                PLAYER_PATH,
+                FakePlayer {
+                    title: title.clone(),
+                    calls: calls.clone(),
+                    shuffle: false,
+                },
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mut source = MprisSource::with_address(bus.address.clone());
+        let (tx, rx) = mpsc::channel();
+        source.start(tx).unwrap();
+
+        let sessions = source.sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].app_id, "fake");
+        assert_eq!(sessions[0].status, Some(PlaybackStatus::Playing));
+        assert!(sessions[0].current);
+        let timeline = sessions[0].timeline.unwrap();
+        assert_eq!(timeline.position, Duration::from_secs(12));
+        assert_eq!(timeline.duration, Some(Duration::from_secs(180)));
+        assert_eq!(
+            source.session_snapshot("fake").unwrap().title.as_deref(),
+            Some("First")
+        );
+        assert_eq!(
+            sessions[0].media.as_ref().unwrap().title.as_deref(),
+            Some("First")
+        );
+
+        source.control(None, Command::Next).unwrap();
+        source
+            .control(Some("fake"), Command::Seek(Duration::from_secs(30)))
+            .unwrap();
+        assert!(source.control(Some("missing"), Command::Play).is_err());
+        assert_eq!(sessions[0].shuffle, Some(false));
+        assert_eq!(sessions[0].repeat, Some(RepeatMode::List));
+        assert_eq!(sessions[0].rate, Some(1.0));
+        source.control(None, Command::Shuffle(true)).unwrap();
+        assert_eq!(source.sessions()[0].shuffle, Some(true));
+        // The player announces the change like any other property change
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(SourceEvent::PropertiesChanged)
+        );
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["Next", "SetPosition /fake/track/1 30000000"]
+        );
+
+        let snap = source.snapshot().unwrap();
+        assert_eq!(snap.title.as_deref(), Some("First"));
+        assert_eq!(snap.artist.as_deref(), Some("Fake Artist"));
+        assert_eq!(snap.track_number, Some(3));
+
+        *title.lock().unwrap() = "Second".to_string();
+        let changed: HashMap<&str, Value<'_>> = HashMap::new();
+        player
+            .emit_signal(
+                None::<&str>,
+                PLAYER_PATH,
+                PROPERTIES_IFACE,
+                "PropertiesChanged",
+                &(PLAYER_IFACE, changed, vec!["Metadata"]),
+            )
+            .unwrap();
+
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(SourceEvent::PropertiesChanged)
+        );
+        assert_eq!(source.snapshot().unwrap().title.as_deref(), Some("Second"));
+
+        player
+            .emit_signal(
+                None::<&str>,
+                PLAYER_PATH,
+                PLAYER_IFACE,
+                "Seeked",
+                &(30_000_000i64,),
+            )
+            .unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(SourceEvent::TimelineChanged)
+        );
+
+        // The player leaving the bus is reported as a session change
+        drop(player);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            let event = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            if event == SourceEvent::SessionSwitched {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline);
+        }
+        assert_eq!(source.snapshot(), None);
+
+        // Stopping ends the listener thread, which drops the event sender
+        source.stop();
+        while rx.recv_timeout(Duration::from_secs(5)).is_ok() {}
+        assert!(matches!(
+            rx.try_recv(),
+            Err(mpsc::TryRecvError::Disconnected)
+        ));
+    }
+}
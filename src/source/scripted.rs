@@ -0,0 +1,116 @@
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{Command, MediaSource, SourceError, SourceEvent};
+use crate::state::{MediaSnapshot, SessionSnapshot};
+
+#[derive(Default)]
+pub(crate) struct Script {
+    pub(crate) current: Option<MediaSnapshot>,
+    pub(crate) sessions: Vec<SessionSnapshot>,
+    pub(crate) fail_start: Option<SourceError>,
+    pub(crate) events: Option<Sender<SourceEvent>>,
+    pub(crate) seen: Vec<SourceEvent>,
+    // Every command the watcher issued, with the session it targeted
+    pub(crate) commands: Vec<(Option<String>, Command)>,
+    pub(crate) fail_control: Option<SourceError>,
+    pub(crate) starts: usize,
+    pub(crate) stops: usize,
+}
+
+/// In-memory source driven by tests through a `ScriptHandle`.
+pub(crate) struct ScriptedSource {
+    script: Arc<Mutex<Script>>,
+}
+
+/// Test-side handle used to change what the source reports and to fire events.
+#[derive(Clone)]
+pub(crate) struct ScriptHandle {
+    script: Arc<Mutex<Script>>,
+}
+
+pub(crate) fn scripted() -> (ScriptedSource, ScriptHandle) {
+    let script = Arc::new(Mutex::new(Script::default()));
+    (
+        ScriptedSource {
+            script: script.clone(),
+        },
+        ScriptHandle { script },
+    )
+}
+
+impl ScriptHandle {
+    pub(crate) fn script(&self) -> MutexGuard<'_, Script> {
+        self.script.lock().unwrap()
+    }
+
+    pub(crate) fn set(&self, snapshot: Option<MediaSnapshot>) {
+        self.script().current = snapshot;
+    }
+
+    /// Sends `event` to the watcher; returns false when the source isn't started.
+    pub(crate) fn emit(&self, event: SourceEvent) -> bool {
+        match self.script().events {
+            Some(ref tx) => tx.send(event).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Sets the snapshot and announces it the way a player would.
+    pub(crate) fn play(&self, snapshot: Option<MediaSnapshot>) -> bool {
+        self.set(snapshot);
+        self.emit(SourceEvent::PropertiesChanged)
+    }
+}
+
+impl MediaSource for ScriptedSource {
+    fn name(&self) -> &'static str {
+        "scripted"
+    }
+
+    fn start(&mut self, events: Sender<SourceEvent>) -> Result<(), SourceError> {
+        let mut script = self.script.lock().unwrap();
+        script.starts += 1;
+        if let Some(err) = script.fail_start.clone() {
+            return Err(err);
+        }
+        script.events = Some(events);
+        Ok(())
+    }
+
+    fn on_event(&mut self, event: SourceEvent) {
+        self.script.lock().unwrap().seen.push(event);
+    }
+
+    fn stop(&mut self) {
+        let mut script = self.script.lock().unwrap();
+        script.stops += 1;
+        script.events = None;
+    }
+
+    fn snapshot(&self) -> Option<MediaSnapshot> {
+        self.script.lock().unwrap().current.clone()
+    }
+
+    fn session_snapshot(&self, app_id: &str) -> Option<MediaSnapshot> {
+        let script = self.script.lock().unwrap();
+        script
+            .sessions
+            .iter()
+            .find(|s| s.app_id == app_id)
+            .and_then(|s| s.media.clone())
+    }
+
+    fn sessions(&self) -> Vec<SessionSnapshot> {
+        self.script.lock().unwrap().sessions.clone()
+    }
+
+    fn control(&self, app_id: Option<&str>, command: Command) -> Result<(), SourceError> {
+        let mut script = self.script.lock().unwrap();
+        if let Some(err) = script.fail_control.clone() {
+            return Err(err);
+        }
+        script.commands.push((app_id.map(String::from), command));
+        Ok(())
+    }
+}
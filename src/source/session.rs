@@ -0,0 +1,268 @@
+use std::sync::mpsc::Sender;
+
+use debug_print::debug_eprintln;
+
+use super::{SourceError, SourceEvent};
+
+/// The parts of a backend the session tracker needs: which sessions exist, which
+/// one is current, and how to (un)subscribe to a session's change notifications.
+pub(crate) trait SessionHost {
+    type Session: PartialEq;
+    type Token;
+
+    fn current_session(&self) -> Option<Self::Session>;
+
+    fn sessions(&self) -> Vec<Self::Session>;
+
+    fn subscribe(
+        &self,
+        session: &Self::Session,
+        events: &Sender<SourceEvent>,
+    ) -> Result<Self::Token, SourceError>;
+
+    fn unsubscribe(&self, session: &Self::Session, token: Self::Token);
+}
+
+/// Keeps change subscriptions on every session the host reports (always including
+/// the current one), dropping subscriptions for sessions that went away.
+pub(crate) struct SessionTracker<S, T> {
+    subscribed: Vec<(S, T)>,
+}
+
+impl<S: PartialEq, T> SessionTracker<S, T> {
+    pub(crate) fn new() -> Self {
+        SessionTracker {
+            subscribed: Vec::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn sessions(&self) -> Vec<&S> {
+        self.subscribed.iter().map(|(session, _)| session).collect()
+    }
+
+    /// Re-targets the subscriptions at the host's current set of sessions.
+    pub(crate) fn follow<H>(&mut self, host: &H, events: &Sender<SourceEvent>)
+    where
+        H: SessionHost<Session = S, Token = T>,
+    {
+        let mut wanted = host.sessions();
+        if let Some(current) = host.current_session()
+            && !wanted.contains(&current)
+        {
+            wanted.push(current);
+        }
+
+        let (kept, gone): (Vec<_>, Vec<_>) = std::mem::take(&mut self.subscribed)
+            .into_iter()
+            .partition(|(session, _)| wanted.contains(session));
+        for (session, token) in gone {
+            host.unsubscribe(&session, token);
+        }
+        self.subscribed = kept;
+
+        for session in wanted {
+            if self.subscribed.iter().any(|(s, _)| *s == session) {
+                continue;
+            }
+            match host.subscribe(&session, events) {
+                Ok(token) => self.subscribed.push((session, token)),
+                Err(_err) => {
+                    debug_eprintln!("m_nowplaying: failed to follow session: {}", _err);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn release<H>(&mut self, host: &H)
+    where
+        H: SessionHost<Session = S, Token = T>,
+    {
+        for (session, token) in self.subscribed.drain(..) {
+            host.unsubscribe(&session, token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::sync::mpsc;
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        Subscribe(&'static str, u32),
+        Unsubscribe(&'static str, u32),
+    }
+
+    #[derive(Default)]
+    struct MockHost {
+        current: RefCell<Option<&'static str>>,
+        listed: RefCell<Vec<&'static str>>,
+        refuse: RefCell<bool>,
+        next_token: RefCell<u32>,
+        calls: RefCell<Vec<Call>>,
+    }
+
+    impl MockHost {
+        fn switch_to(&self, session: Option<&'static str>) {
+            *self.current.borrow_mut() = session;
+        }
+
+        fn list(&self, sessions: Vec<&'static str>) {
+            *self.listed.borrow_mut() = sessions;
+        }
+
+        fn take_calls(&self) -> Vec<Call> {
+            self.calls.take()
+        }
+    }
+
+    impl SessionHost for MockHost {
+        type Session = &'static str;
+        type Token = u32;
+
+        fn current_session(&self) -> Option<&'static str> {
+            *self.current.borrow()
+        }
+
+        fn sessions(&self) -> Vec<&'static str> {
+            self.listed.borrow().clone()
+        }
+
+        fn subscribe(
+            &self,
+            session: &&'static str,
+            _events: &Sender<SourceEvent>,
+        ) -> Result<u32, SourceError> {
+            if *self.refuse.borrow() {
+                return Err(SourceError::new("refused"));
+            }
+            let mut next = self.next_token.borrow_mut();
+            *next += 1;
+            self.calls
+                .borrow_mut()
+                .push(Call::Subscribe(session, *next));
+            Ok(*next)
+        }
+
+        fn unsubscribe(&self, session: &&'static str, token: u32) {
+            self.calls
+                .borrow_mut()
+                .push(Call::Unsubscribe(session, token));
+        }
+    }
+
+    #[test]
+    fn follows_session_switches_and_drops_old_token() {
+        let host = MockHost::default();
+        let (tx, _rx) = mpsc::channel();
+        let mut tracker = SessionTracker::new();
+
+        host.switch_to(Some("spotify"));
+        tracker.follow(&host, &tx);
+        assert_eq!(host.take_calls(), vec![Call::Subscribe("spotify", 1)]);
+
+        host.switch_to(Some("chrome"));
+        tracker.follow(&host, &tx);
+        assert_eq!(
+            host.take_calls(),
+            vec![
+                Call::Unsubscribe("spotify", 1),
+                Call::Subscribe("chrome", 2)
+            ]
+        );
+        assert_eq!(tracker.sessions(), vec![&"chrome"]);
+    }
+
+    #[test]
+    fn same_session_is_not_resubscribed() {
+        let host = MockHost::default();
+        let (tx, _rx) = mpsc::channel();
+        let mut tracker = SessionTracker::new();
+
+        host.switch_to(Some("spotify"));
+        tracker.follow(&host, &tx);
+        tracker.follow(&host, &tx);
+        assert_eq!(host.take_calls(), vec![Call::Subscribe("spotify", 1)]);
+    }
+
+    #[test]
+    fn losing_the_session_unsubscribes() {
+        let host = MockHost::default();
+        let (tx, _rx) = mpsc::channel();
+        let mut tracker = SessionTracker::new();
+
+        host.switch_to(Some("spotify"));
+        tracker.follow(&host, &tx);
+        host.switch_to(None);
+        tracker.follow(&host, &tx);
+        assert_eq!(
+            host.take_calls(),
+            vec![
+                Call::Subscribe("spotify", 1),
+                Call::Unsubscribe("spotify", 1)
+            ]
+        );
+        assert!(tracker.sessions().is_empty());
+
+        // Nothing left to release
+        tracker.release(&host);
+        assert!(host.take_calls().is_empty());
+    }
+
+    #[test]
+    fn failed_subscribe_is_retried_on_next_follow() {
+        let host = MockHost::default();
+        let (tx, _rx) = mpsc::channel();
+        let mut tracker = SessionTracker::new();
+
+        host.switch_to(Some("spotify"));
+        *host.refuse.borrow_mut() = true;
+        tracker.follow(&host, &tx);
+        assert!(tracker.sessions().is_empty());
+
+        *host.refuse.borrow_mut() = false;
+        tracker.follow(&host, &tx);
+        assert_eq!(host.take_calls(), vec![Call::Subscribe("spotify", 1)]);
+    }
+
+    #[test]
+    fn every_listed_session_is_followed() {
+        let host = MockHost::default();
+        let (tx, _rx) = mpsc::channel();
+        let mut tracker = SessionTracker::new();
+
+        host.list(vec!["spotify", "chrome"]);
+        host.switch_to(Some("chrome"));
+        tracker.follow(&host, &tx);
+        assert_eq!(
+            host.take_calls(),
+            vec![Call::Subscribe("spotify", 1), Call::Subscribe("chrome", 2)]
+        );
+
+        // Focus moving between known sessions needs no new subscriptions
+        host.switch_to(Some("spotify"));
+        tracker.follow(&host, &tx);
+        assert!(host.take_calls().is_empty());
+
+        // A closed session is dropped, a new one picked up
+        host.list(vec!["spotify", "teams"]);
+        tracker.follow(&host, &tx);
+        assert_eq!(
+            host.take_calls(),
+            vec![Call::Unsubscribe("chrome", 2), Call::Subscribe("teams", 3)]
+        );
+
+        tracker.release(&host);
+        assert_eq!(
+            host.take_calls(),
+            vec![
+                Call::Unsubscribe("spotify", 1),
+                Call::Unsubscribe("teams", 3)
+            ]
+        );
+    }
+}
@@ -0,0 +1,1433 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{
+    Arc, Condvar, Mutex, MutexGuard, OnceLock,
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Sender},
+};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::event::{Change, Diff, EventLog, WaitSpec};
+use crate::field::Field;
+use crate::names::AppNames;
+use crate::policy::{Selection, SessionSelector};
+use crate::source::{Command, SourceError, SourceEvent};
+use crate::template::FormatOptions;
+use crate::timeline::{Clock, SystemClock, Timeline};
+use crate::watcher::{Health, WatcherState};
+
+// Small shared state used to coordinate wait_for_media/halt and expose metadata
+#[derive(Default)]
+pub(crate) struct MediaState {
+    // Core metadata
+    pub(crate) media: MediaSnapshot,
+    // The session `media` came from, and its playback state
+    pub(crate) app_id: Option<String>,
+    pub(crate) status: Option<PlaybackStatus>,
+    pub(crate) timeline: Option<Timeline>,
+    pub(crate) shuffle: Option<bool>,
+    pub(crate) repeat: Option<RepeatMode>,
+    pub(crate) rate: Option<f64>,
+
+    // Thumbnail handling
+    pub(crate) thumbnail_path: Option<String>, // cache of last written file
+
+    // Every session the source knows about, refreshed alongside `media`
+    pub(crate) sessions: Vec<SessionSnapshot>,
+    // Decides which of `sessions` feeds `media`
+    pub(crate) selector: SessionSelector,
+    // Display names for source app ids
+    pub(crate) names: AppNames,
+    // Settings for the format export
+    pub(crate) format: FormatOptions,
+
+    // Changes wait this long for more to follow before bumping `version`
+    pub(crate) settle: Duration,
+    pub(crate) pending: Option<Pending>,
+    // An empty reading has to last this long before the metadata is cleared
+    pub(crate) clear_grace: Duration,
+    // When the current run of empty readings began
+    pub(crate) clearing: Option<SystemTime>,
+
+    // Control
+    pub(crate) version: u64,
+    // What produced `version` and the changes before it
+    pub(crate) events: EventLog,
+    // When `version` last moved
+    pub(crate) updated: Option<SystemTime>,
+    // Scripts waiting for changes, by listener id; "" is the anonymous one
+    pub(crate) listeners: HashMap<String, Listener>,
+    // Kept up to date by the watcher thread
+    pub(crate) health: Health,
+    // Notifications the watcher got from the source
+    pub(crate) received: u64,
+    // Updates folded into a change that was still settling
+    pub(crate) coalesced: u64,
+    pub(crate) query: QueryMode,
+    // How many fetches the watcher has done, and when it did the last one
+    pub(crate) fetches: u64,
+    pub(crate) fetched: Option<SystemTime>,
+    // When the track functions or a command last needed the watcher while nobody
+    // listened. A watcher running only for them ends once this is FETCH_IDLE old.
+    pub(crate) queried: Option<SystemTime>,
+}
+
+// No title and no artist: a player between tracks, or none at all
+fn is_blank(media: &MediaSnapshot) -> bool {
+    Field::Title.text(media).is_empty() && Field::Artist.text(media).is_empty()
+}
+
+// Replaces the track metadata with `new`, noting what changed in `diff`
+fn merge_media(state: &mut MediaState, diff: &mut Diff, new: MediaSnapshot) {
+    let media = &mut state.media;
+    diff.merge(Field::Title, &mut media.title, new.title);
+    diff.merge(Field::Artist, &mut media.artist, new.artist);
+    diff.merge(Field::AlbumTitle, &mut media.album_title, new.album_title);
+    diff.merge(
+        Field::AlbumArtist,
+        &mut media.album_artist,
+        new.album_artist,
+    );
+    diff.merge(Field::Genres, &mut media.genres, new.genres);
+    diff.merge(Field::Subtitle, &mut media.subtitle, new.subtitle);
+    diff.merge(
+        Field::TrackNumber,
+        &mut media.track_number,
+        new.track_number,
+    );
+    diff.merge(
+        Field::AlbumTrackCount,
+        &mut media.album_track_count,
+        new.album_track_count,
+    );
+    diff.merge(
+        Field::PlaybackType,
+        &mut media.playback_type,
+        new.playback_type,
+    );
+    // Thumbnail bytes: if changed, clear old file
+    if media.thumbnail_bytes != new.thumbnail_bytes {
+        if let Some(old_path) = state.thumbnail_path.take() {
+            let _ = std::fs::remove_file(old_path);
+        }
+        media.thumbnail_bytes = new.thumbnail_bytes;
+        diff.thumbnail = true;
+    }
+}
+
+// Longest a burst can hold a change back, in settle windows
+const MAX_SETTLE_WINDOWS: u32 = 4;
+
+// A change waiting out the settle window, with when the burst began and last grew
+pub(crate) struct Pending {
+    diff: Diff,
+    first: SystemTime,
+    last: SystemTime,
+}
+
+#[derive(Default, Clone, Debug, PartialEq)]
+pub(crate) struct MediaSnapshot {
+    pub(crate) title: Option<String>,
+    pub(crate) artist: Option<String>,
+    pub(crate) album_title: Option<String>,
+    pub(crate) album_artist: Option<String>,
+    pub(crate) genres: Option<Vec<String>>,
+    pub(crate) subtitle: Option<String>,
+    pub(crate) track_number: Option<u32>,
+    pub(crate) album_track_count: Option<u32>,
+    pub(crate) playback_type: Option<String>,
+    pub(crate) thumbnail_bytes: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PlaybackStatus {
+    Closed,
+    Opened,
+    Changing,
+    Stopped,
+    Playing,
+    Paused,
+}
+
+impl PlaybackStatus {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            PlaybackStatus::Closed => "closed",
+            PlaybackStatus::Opened => "opened",
+            PlaybackStatus::Changing => "changing",
+            PlaybackStatus::Stopped => "stopped",
+            PlaybackStatus::Playing => "playing",
+            PlaybackStatus::Paused => "paused",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RepeatMode {
+    None,
+    Track,
+    List,
+}
+
+impl RepeatMode {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            RepeatMode::None => "none",
+            RepeatMode::Track => "track",
+            RepeatMode::List => "list",
+        }
+    }
+
+    pub(crate) fn parse(name: &str) -> Option<RepeatMode> {
+        [RepeatMode::None, RepeatMode::Track, RepeatMode::List]
+            .into_iter()
+            .find(|mode| mode.as_str().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// What the track functions do while nobody is listening.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum QueryMode {
+    /// Report nothing; only listeners keep the metadata current.
+    #[default]
+    Live,
+    /// Read the player right away and report what it says.
+    Fetch,
+}
+
+impl QueryMode {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            QueryMode::Live => "live",
+            QueryMode::Fetch => "fetch",
+        }
+    }
+
+    pub(crate) fn parse(name: &str) -> Option<QueryMode> {
+        [QueryMode::Live, QueryMode::Fetch]
+            .into_iter()
+            .find(|mode| mode.as_str().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+// A fetch this recent is good enough for the next query too, so reading several
+// fields in a row asks the player only once
+pub(crate) const FETCH_REUSE: Duration = Duration::from_secs(1);
+
+// How long the watcher stays up after the last query while nobody is listening
+pub(crate) const FETCH_IDLE: Duration = Duration::from_secs(30);
+
+// How fast the position moves: the playback rate while playing, otherwise not at all
+fn speed(status: Option<PlaybackStatus>, rate: Option<f64>) -> f64 {
+    if status == Some(PlaybackStatus::Playing) {
+        rate.unwrap_or(1.0)
+    } else {
+        0.0
+    }
+}
+
+/// One media session as reported by the source, current or not.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub(crate) struct SessionSnapshot {
+    pub(crate) app_id: String,
+    pub(crate) status: Option<PlaybackStatus>,
+    pub(crate) timeline: Option<Timeline>,
+    pub(crate) shuffle: Option<bool>,
+    pub(crate) repeat: Option<RepeatMode>,
+    pub(crate) rate: Option<f64>,
+    // Whether the backend considers this its current session
+    pub(crate) current: bool,
+    // Session metadata never carries thumbnail bytes
+    pub(crate) media: Option<MediaSnapshot>,
+}
+
+// App ids match case-insensitively, with or without their extension ("spotify" matches "Spotify.exe")
+pub(crate) fn app_id_matches(app_id: &str, key: &str) -> bool {
+    let key = key.trim();
+    let stem = app_id.rsplit_once('.').map_or(app_id, |(stem, _)| stem);
+    app_id.eq_ignore_ascii_case(key) || stem.eq_ignore_ascii_case(key)
+}
+
+// Looks a session up by 1-based index or by source app id
+pub(crate) fn find_session<'a>(
+    sessions: &'a [SessionSnapshot],
+    key: &str,
+) -> Option<&'a SessionSnapshot> {
+    let key = key.trim();
+    if let Ok(index) = key.parse::<usize>() {
+        return index.checked_sub(1).and_then(|i| sessions.get(i));
+    }
+    sessions.iter().find(|s| app_id_matches(&s.app_id, key))
+}
+
+impl MediaState {
+    pub(crate) fn speed(&self) -> f64 {
+        speed(self.status, self.rate)
+    }
+}
+
+impl SessionSnapshot {
+    pub(crate) fn speed(&self) -> f64 {
+        speed(self.status, self.rate)
+    }
+}
+
+/// How a `wait_for_media` call was released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WaitOutcome {
+    /// The first change after the version the waiter had seen.
+    Changed(Change),
+    Cancelled,
+    /// Nothing the waiter wanted happened within its timeout.
+    TimedOut,
+}
+
+/// One `wait_for_media` registration. Named ones remember how far they got, so a
+/// script can re-arm without passing the version back. Halting removes it; the
+/// next `wait_for_media` under the same id starts afresh.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Listener {
+    // The last version handed to it
+    pub(crate) seen: u64,
+    // Held by every call waiting as this listener. A waiter that no longer finds
+    // its own token in the map was halted, even if the id registered again since.
+    waiters: Arc<()>,
+}
+
+impl Listener {
+    // How many calls are waiting as this listener right now
+    fn waiting(&self) -> usize {
+        Arc::strong_count(&self.waiters) - 1
+    }
+}
+
+// A command waiting for the watcher thread, with where to send its outcome
+pub(crate) type PendingCommand = (Command, Sender<Result<(), SourceError>>);
+
+/// The metadata plus the condvar waiters block on. One instance backs the DLL;
+/// tests create their own so they don't share state.
+pub(crate) struct SharedMedia {
+    state: Mutex<MediaState>,
+    cvar: Condvar,
+    listening: AtomicBool,
+    // Lets the DLL side poke the running watcher, e.g. after a policy change
+    watcher: Mutex<Option<Sender<SourceEvent>>>,
+    // Transport commands run on the watcher thread, which owns the source
+    commands: Mutex<VecDeque<PendingCommand>>,
+    clock: Arc<dyn Clock>,
+}
+
+static GLOBAL_MEDIA: OnceLock<SharedMedia> = OnceLock::new();
+
+// Returns the global shared state, initializing to defaults if necessary
+pub(crate) fn ensure_state() -> &'static SharedMedia {
+    GLOBAL_MEDIA.get_or_init(SharedMedia::new)
+}
+
+impl SharedMedia {
+    pub(crate) fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    pub(crate) fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        SharedMedia {
+            state: Mutex::new(MediaState::default()),
+            cvar: Condvar::new(),
+            listening: AtomicBool::new(false),
+            watcher: Mutex::new(None),
+            commands: Mutex::new(VecDeque::new()),
+            clock,
+        }
+    }
+
+    pub(crate) fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, MediaState> {
+        self.state.lock().unwrap()
+    }
+
+    pub(crate) fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_listening(&self, listening: bool) {
+        self.listening.store(listening, Ordering::SeqCst);
+    }
+
+    pub(crate) fn attach_watcher(&self, events: Sender<SourceEvent>) {
+        *self.watcher.lock().unwrap() = Some(events);
+    }
+
+    pub(crate) fn detach_watcher(&self) {
+        let mut watcher = self.watcher.lock().unwrap();
+        *watcher = None;
+        // Nobody is left to run these; dropping them fails their callers right away
+        self.commands.lock().unwrap().clear();
+    }
+
+    /// Whether a watcher thread is taking events.
+    pub(crate) fn has_watcher(&self) -> bool {
+        self.watcher.lock().unwrap().is_some()
+    }
+
+    /// Notes that a query needs the watcher, keeping it up for another
+    /// `FETCH_IDLE`. Call it before starting the watcher, so it does not retire
+    /// in between.
+    pub(crate) fn note_query(&self) {
+        self.lock().queried = Some(self.now());
+    }
+
+    /// How long until a watcher kept up only for queries may end; `None` while
+    /// somebody is listening or before the first query.
+    pub(crate) fn idle_in(&self) -> Option<Duration> {
+        let state = self.lock();
+        Self::idle_deadline(&state, self.is_listening())
+            .map(|deadline| deadline.duration_since(self.now()).unwrap_or_default())
+    }
+
+    fn idle_deadline(state: &MediaState, listening: bool) -> Option<SystemTime> {
+        if listening {
+            return None;
+        }
+        Some(state.queried? + FETCH_IDLE)
+    }
+
+    /// Detaches the watcher if it has been idle for `FETCH_IDLE`, so the next
+    /// query starts a new one. False when it was needed again in the meantime.
+    pub(crate) fn retire_watcher(&self) -> bool {
+        let state = self.lock();
+        if Self::idle_deadline(&state, self.is_listening())
+            .is_none_or(|deadline| deadline > self.now())
+        {
+            return false;
+        }
+        // Still holding the state, so note_query cannot slip in before this
+        self.detach_watcher();
+        true
+    }
+
+    /// Queues `event` for the watcher; false when no watcher is running.
+    pub(crate) fn notify_watcher(&self, event: SourceEvent) -> bool {
+        match *self.watcher.lock().unwrap() {
+            Some(ref tx) => tx.send(event).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Hands `command` to the watcher thread and waits up to `timeout` for its outcome.
+    pub(crate) fn control(&self, command: Command, timeout: Duration) -> Result<(), SourceError> {
+        let (tx, rx) = mpsc::channel();
+        {
+            // Holding the watcher lock keeps detach_watcher from racing the queue
+            let watcher = self.watcher.lock().unwrap();
+            let Some(ref events) = *watcher else {
+                return Err(SourceError::new("media watcher is not running"));
+            };
+            let mut commands = self.commands.lock().unwrap();
+            commands.push_back((command, tx));
+            if events.send(SourceEvent::CommandQueued).is_err() {
+                commands.pop_back();
+                return Err(SourceError::new("media watcher is not running"));
+            }
+        }
+        match rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                Err(SourceError::new("timed out waiting for the player"))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(SourceError::new("media watcher stopped"))
+            }
+        }
+    }
+
+    pub(crate) fn next_command(&self) -> Option<PendingCommand> {
+        self.commands.lock().unwrap().pop_front()
+    }
+
+    // Stores the session list and decides which session feeds the current
+    // track. The list is informational; replacing it does not wake waiters.
+    pub(crate) fn select_session(&self, sessions: Vec<SessionSnapshot>) -> Selection {
+        let mut state = self.lock();
+        let selection = state.selector.select(&sessions);
+        state.sessions = sessions;
+        selection
+    }
+
+    // Publishes the selected session's metadata and playback state; waiters wake
+    // once if anything changed. Timeline updates only count when the duration
+    // moves, so a position report alone does not wake anyone.
+    pub(crate) fn update_state_with(
+        &self,
+        new: Option<MediaSnapshot>,
+        session: Option<&SessionSnapshot>,
+    ) {
+        let mut guard = self.lock();
+        let state = &mut *guard;
+        let mut diff = Diff::default();
+        let app_id = session.map(|s| s.app_id.clone());
+        let switched = state.app_id != app_id;
+        if switched {
+            state.app_id = app_id;
+            diff.fields.insert(Field::AppId);
+            diff.fields.insert(Field::Source);
+        }
+        diff.merge(
+            Field::Status,
+            &mut state.status,
+            session.and_then(|s| s.status),
+        );
+        let timeline = session.and_then(|s| s.timeline);
+        let mut duration = state.timeline.and_then(|t| t.duration);
+        diff.merge(
+            Field::Duration,
+            &mut duration,
+            timeline.and_then(|t| t.duration),
+        );
+        state.timeline = timeline;
+        diff.merge(
+            Field::Shuffle,
+            &mut state.shuffle,
+            session.and_then(|s| s.shuffle),
+        );
+        diff.merge(
+            Field::Repeat,
+            &mut state.repeat,
+            session.and_then(|s| s.repeat),
+        );
+        diff.merge(Field::Rate, &mut state.rate, session.and_then(|s| s.rate));
+
+        let now = self.now();
+        // Some players report nothing for a moment on every skip; keep the old
+        // metadata for the grace period unless the gap lasts. A different player
+        // has nothing to do with the old title, so that clears at once.
+        let blank = new.as_ref().is_none_or(is_blank);
+        if blank && !switched && !state.clear_grace.is_zero() && !is_blank(&state.media) {
+            let since = *state.clearing.get_or_insert(now);
+            if now >= since + state.clear_grace {
+                state.clearing = None;
+                merge_media(state, &mut diff, MediaSnapshot::default());
+            }
+        } else {
+            state.clearing = None;
+            // No metadata available clears it all
+            merge_media(state, &mut diff, new.unwrap_or_default());
+        }
+        self.commit(state, diff, now);
+    }
+
+    // Publishes `diff` now, or holds it back while a settle window is open
+    fn commit(&self, state: &mut MediaState, diff: Diff, now: SystemTime) {
+        if diff.is_empty() {
+            return;
+        }
+        if state.settle.is_zero() {
+            // The window was just turned off; whatever it held goes out with this change
+            let diff = match state.pending.take() {
+                Some(mut pending) => {
+                    state.coalesced += 1;
+                    pending.diff.absorb(diff);
+                    pending.diff
+                }
+                None => diff,
+            };
+            self.publish(state, diff);
+            return;
+        }
+        // Hold the change back until the burst is over; readers already see the new values
+        match state.pending {
+            Some(ref mut pending) => {
+                pending.diff.absorb(diff);
+                pending.last = now;
+                state.coalesced += 1;
+            }
+            None => {
+                state.pending = Some(Pending {
+                    diff,
+                    first: now,
+                    last: now,
+                })
+            }
+        }
+        // A source that never goes quiet still gets its changes out
+        if Self::settle_deadline(state).is_some_and(|deadline| deadline <= now)
+            && let Some(pending) = state.pending.take()
+        {
+            self.publish(state, pending.diff);
+        }
+    }
+
+    // Bumps the version for `diff` and wakes the waiters
+    fn publish(&self, state: &mut MediaState, mut diff: Diff) {
+        diff.cleared = diff.touches_track() && state.media == MediaSnapshot::default();
+        state.version = state.version.wrapping_add(1);
+        state.updated = Some(self.now());
+        state.events.push(Change {
+            version: state.version,
+            kind: diff.kind(),
+            fields: diff.fields,
+            thumbnail: diff.thumbnail,
+        });
+        self.cvar.notify_all();
+    }
+
+    // When the held back change is due: once the source has been quiet for the
+    // settle window, but never later than a few windows after the burst began
+    fn settle_deadline(state: &MediaState) -> Option<SystemTime> {
+        let pending = state.pending.as_ref()?;
+        let quiet = pending.last + state.settle;
+        let cap = pending.first + state.settle * MAX_SETTLE_WINDOWS;
+        Some(quiet.min(cap))
+    }
+
+    // When the empty reading has lasted long enough to clear the metadata
+    fn clear_deadline(state: &MediaState) -> Option<SystemTime> {
+        Some(state.clearing? + state.clear_grace)
+    }
+
+    /// How long until held back work is due: a settled change or a clear.
+    pub(crate) fn due_in(&self) -> Option<Duration> {
+        let state = self.lock();
+        let deadline = match (Self::settle_deadline(&state), Self::clear_deadline(&state)) {
+            (Some(settle), Some(clear)) => settle.min(clear),
+            (deadline, None) | (None, deadline) => deadline?,
+        };
+        Some(deadline.duration_since(self.now()).unwrap_or_default())
+    }
+
+    /// Does whatever held back work is due; true if a change was published.
+    pub(crate) fn run_due(&self) -> bool {
+        let mut guard = self.lock();
+        let state = &mut *guard;
+        let now = self.now();
+        let version = state.version;
+        if Self::clear_deadline(state).is_some_and(|deadline| deadline <= now) {
+            state.clearing = None;
+            let mut diff = Diff::default();
+            merge_media(state, &mut diff, MediaSnapshot::default());
+            self.commit(state, diff, now);
+        }
+        if Self::settle_deadline(state).is_some_and(|deadline| deadline <= now)
+            && let Some(pending) = state.pending.take()
+        {
+            self.publish(state, pending.diff);
+        }
+        state.version != version
+    }
+
+    // Blocks until there is a change after version `spec.seen` to one of the
+    // fields in `spec.filter`, until the listener is halted, or until
+    // `spec.timeout` runs out. Without a version, named listeners carry on from
+    // the last change they got and anonymous ones from the current version. A
+    // caller that fell behind gets the first change it missed right away.
+    pub(crate) fn wait_for_change(&self, spec: WaitSpec) -> WaitOutcome {
+        let deadline = spec.timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.lock();
+        let version = state.version;
+        let id = spec.listener.unwrap_or_default();
+        // A new listener starts from now rather than replaying the event log
+        let listener = state
+            .listeners
+            .entry(id.clone())
+            .or_insert_with(|| Listener {
+                seen: version,
+                ..Default::default()
+            });
+        if id.is_empty() {
+            listener.seen = version;
+        }
+        let seen = spec.seen.unwrap_or(listener.seen);
+        let token = listener.waiters.clone();
+        let halted = |state: &MediaState| {
+            state
+                .listeners
+                .get(&id)
+                .is_none_or(|l| !Arc::ptr_eq(&l.waiters, &token))
+        };
+
+        loop {
+            if let Some(change) = state.events.after(seen, &spec.filter) {
+                if !halted(&state)
+                    && let Some(listener) = state.listeners.get_mut(&id)
+                {
+                    listener.seen = change.version;
+                }
+                return WaitOutcome::Changed(change);
+            }
+            if halted(&state) {
+                return WaitOutcome::Cancelled;
+            }
+            state = match deadline {
+                None => self.cvar.wait(state).unwrap(),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return WaitOutcome::TimedOut;
+                    }
+                    self.cvar.wait_timeout(state, left).unwrap().0
+                }
+            };
+        }
+    }
+
+    /// Has the watcher read the player while nobody is listening, and waits up to
+    /// `timeout` for it. A recent enough fetch is reused. False when the watcher
+    /// is not running, could not read the player or did not get to it in time.
+    pub(crate) fn fetch(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let target = {
+            let state = self.lock();
+            let now = self.now();
+            if state
+                .fetched
+                .is_some_and(|at| now.duration_since(at).unwrap_or_default() < FETCH_REUSE)
+            {
+                return true;
+            }
+            // Nothing to read from until the source is back
+            if state.health.state == WatcherState::Retrying {
+                return false;
+            }
+            state.fetches + 1
+        };
+        if !self.notify_watcher(SourceEvent::Fetch) {
+            return false;
+        }
+
+        let mut state = self.lock();
+        while state.fetches < target {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return false;
+            }
+            state = self.cvar.wait_timeout(state, left).unwrap().0;
+        }
+        state.fetched.is_some()
+    }
+
+    // Called by the watcher to answer fetch(), with whether it read the player
+    pub(crate) fn finish_fetch(&self, read: bool) {
+        let mut state = self.lock();
+        state.fetches += 1;
+        state.fetched = read.then(|| self.now());
+        self.cvar.notify_all();
+    }
+
+    // Releases every listener and stops listening
+    pub(crate) fn halt(&self) {
+        let mut state = self.lock();
+        self.set_listening(false);
+        state.listeners.clear();
+        self.cvar.notify_all();
+    }
+
+    // Releases the listener registered as `id`, leaving the others be; halting
+    // the last one stops listening like `halt`. False when there is no such
+    // listener.
+    pub(crate) fn halt_listener(&self, id: &str) -> bool {
+        let mut state = self.lock();
+        if state.listeners.remove(&id.to_ascii_lowercase()).is_none() {
+            return false;
+        }
+        if state.listeners.is_empty() {
+            self.set_listening(false);
+        }
+        self.cvar.notify_all();
+        true
+    }
+
+    /// One line of `key=value` pairs telling what the DLL is up to, for bug
+    /// reports. The last error, which may contain spaces, comes last.
+    pub(crate) fn diagnostics(&self) -> String {
+        let state = self.lock();
+        let ago = |at: Option<SystemTime>| match at {
+            Some(at) => {
+                let ago = self.now().duration_since(at).unwrap_or_default();
+                format!("{:.1}s", ago.as_secs_f64())
+            }
+            None => "never".to_string(),
+        };
+        let health = &state.health;
+        let mut line = format!(
+            "listening={} query={} watcher={} backend={} restarts={} version={} updated={} \
+             received={} coalesced={} thumbnail={} listeners={} fetches={} queried={}",
+            if self.is_listening() { "yes" } else { "no" },
+            state.query.as_str(),
+            health.state.as_str(),
+            health.backend.unwrap_or("none"),
+            health.restarts,
+            state.version,
+            ago(state.updated),
+            state.received,
+            state.coalesced,
+            state.media.thumbnail_bytes.as_ref().map_or(0, Vec::len),
+            state.listeners.values().filter(|l| l.waiting() > 0).count(),
+            state.fetches,
+            ago(state.queried),
+        );
+        if let Some(ref err) = health.last_error {
+            line.push_str(" error=");
+            line.push_str(err);
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::timeline::tests::FakeClock;
+
+    pub(crate) fn track(title: &str, artist: &str) -> MediaSnapshot {
+        MediaSnapshot {
+            title: Some(title.to_string()),
+            artist: Some(artist.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn leaked() -> &'static SharedMedia {
+        Box::leak(Box::new(SharedMedia::new()))
+    }
+
+    #[test]
+    fn identical_snapshot_does_not_bump_version() {
+        let media = SharedMedia::new();
+        media.update_state_with(Some(track("Song", "Band")), None);
+        media.update_state_with(Some(track("Song", "Band")), None);
+        assert_eq!(media.lock().version, 1);
+    }
+
+    #[test]
+    fn any_field_change_bumps_version() {
+        let media = SharedMedia::new();
+        media.update_state_with(Some(track("Song", "Band")), None);
+        let mut enriched = track("Song", "Band");
+        enriched.genres = Some(vec!["Rock".to_string()]);
+        media.update_state_with(Some(enriched), None);
+
+        let state = media.lock();
+        assert_eq!(state.version, 2);
+        assert_eq!(
+            state.media.genres.as_deref(),
+            Some(&["Rock".to_string()][..])
+        );
+    }
+
+    #[test]
+    fn clearing_only_bumps_when_something_was_set() {
+        let media = SharedMedia::new();
+        media.update_state_with(None, None);
+        assert_eq!(media.lock().version, 0);
+
+        media.update_state_with(Some(track("Song", "Band")), None);
+        media.update_state_with(None, None);
+        let state = media.lock();
+        assert_eq!(state.version, 2);
+        assert!(state.media.title.is_none());
+    }
+
+    pub(crate) fn session(app_id: &str, status: PlaybackStatus) -> SessionSnapshot {
+        SessionSnapshot {
+            app_id: app_id.to_string(),
+            status: Some(status),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn status_transitions_bump_version() {
+        let media = SharedMedia::new();
+        let playing = session("Spotify.exe", PlaybackStatus::Playing);
+        let paused = session("Spotify.exe", PlaybackStatus::Paused);
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+        media.update_state_with(Some(track("Song", "Band")), Some(&paused));
+        media.update_state_with(Some(track("Song", "Band")), Some(&paused));
+
+        let state = media.lock();
+        assert_eq!(state.version, 2);
+        assert_eq!(state.status, Some(PlaybackStatus::Paused));
+    }
+
+    #[test]
+    fn switching_source_app_bumps_version() {
+        let media = SharedMedia::new();
+        let spotify = session("Spotify.exe", PlaybackStatus::Playing);
+        let chrome = session("chrome", PlaybackStatus::Playing);
+        media.update_state_with(Some(track("Song", "Band")), Some(&spotify));
+        media.update_state_with(Some(track("Song", "Band")), Some(&chrome));
+
+        let state = media.lock();
+        assert_eq!(state.version, 2);
+        assert_eq!(state.app_id.as_deref(), Some("chrome"));
+    }
+
+    #[test]
+    fn shuffle_repeat_and_rate_changes_bump_version() {
+        let media = SharedMedia::new();
+        let mut playing = session("Spotify.exe", PlaybackStatus::Playing);
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+
+        playing.shuffle = Some(true);
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+        playing.repeat = Some(RepeatMode::List);
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+        playing.rate = Some(1.5);
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+
+        let state = media.lock();
+        assert_eq!(state.version, 4);
+        assert_eq!(state.shuffle, Some(true));
+        assert_eq!(state.repeat, Some(RepeatMode::List));
+        assert_eq!(state.speed(), 1.5);
+    }
+
+    #[test]
+    fn repeat_modes_parse_by_name() {
+        assert_eq!(RepeatMode::parse("Track"), Some(RepeatMode::Track));
+        assert_eq!(RepeatMode::parse(" list "), Some(RepeatMode::List));
+        assert_eq!(RepeatMode::parse("none"), Some(RepeatMode::None));
+        assert_eq!(RepeatMode::parse("all"), None);
+    }
+
+    #[test]
+    fn position_reports_do_not_bump_version() {
+        let clock = Arc::new(FakeClock::new());
+        let media = SharedMedia::with_clock(clock.clone());
+        let mut playing = session("Spotify.exe", PlaybackStatus::Playing);
+        let timeline = |position| Timeline {
+            position: Duration::from_secs(position),
+            duration: Some(Duration::from_secs(200)),
+            updated: clock.now(),
+        };
+        playing.timeline = Some(timeline(10));
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+        assert_eq!(media.lock().version, 1);
+
+        // A seek replaces the sample without waking anyone
+        playing.timeline = Some(timeline(100));
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+        clock.advance(Duration::from_secs(5));
+        let state = media.lock();
+        assert_eq!(state.version, 1);
+        assert_eq!(
+            state
+                .timeline
+                .unwrap()
+                .position_at(media.now(), state.speed()),
+            Duration::from_secs(105)
+        );
+    }
+
+    #[test]
+    fn updated_follows_version_changes() {
+        let clock = Arc::new(FakeClock::new());
+        let media = SharedMedia::with_clock(clock.clone());
+        assert_eq!(media.lock().updated, None);
+        let playing = session("Spotify.exe", PlaybackStatus::Playing);
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+        let first = clock.now();
+
+        clock.advance(Duration::from_secs(5));
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+        assert_eq!(media.lock().updated, Some(first));
+        media.update_state_with(Some(track("Other", "Band")), Some(&playing));
+        assert_eq!(media.lock().updated, Some(clock.now()));
+    }
+
+    #[test]
+    fn diagnostics_fit_on_one_line() {
+        let (media, clock) = settling(300);
+        assert_eq!(
+            media.diagnostics(),
+            "listening=no query=live watcher=stopped backend=none restarts=0 version=0 updated=never \
+             received=0 coalesced=0 thumbnail=0 listeners=0 fetches=0 queried=never"
+        );
+
+        media.set_listening(true);
+        media.update_state_with(Some(track("Song", "Band")), None);
+        let mut artwork = track("Song", "Band");
+        artwork.thumbnail_bytes = Some(vec![0; 512]);
+        media.update_state_with(Some(artwork), None);
+        clock.advance(Duration::from_millis(300));
+        assert!(media.run_due());
+        clock.advance(Duration::from_millis(2500));
+        {
+            let mut state = media.lock();
+            state.health.last_error = Some("no backend".to_string());
+            // Only listeners with a call waiting count
+            state
+                .listeners
+                .insert("idle".to_string(), Listener::default());
+            state
+                .listeners
+                .insert("np".to_string(), Listener::default());
+        }
+        let _waiting = media.lock().listeners["np"].waiters.clone();
+        media.lock().queried = Some(clock.now() - Duration::from_secs(1));
+        assert_eq!(
+            media.diagnostics(),
+            "listening=yes query=live watcher=stopped backend=none restarts=0 version=1 updated=2.5s \
+             received=0 coalesced=1 thumbnail=512 listeners=1 fetches=0 queried=1.0s \
+             error=no backend"
+        );
+    }
+
+    #[test]
+    fn query_watcher_idles_out_only_while_nobody_listens() {
+        let clock = Arc::new(FakeClock::new());
+        let media = SharedMedia::with_clock(clock.clone());
+        assert_eq!(media.idle_in(), None);
+        media.note_query();
+        assert_eq!(media.idle_in(), Some(FETCH_IDLE));
+
+        clock.advance(FETCH_IDLE);
+        media.set_listening(true);
+        assert_eq!(media.idle_in(), None);
+        assert!(!media.retire_watcher());
+        media.set_listening(false);
+        assert_eq!(media.idle_in(), Some(Duration::ZERO));
+        assert!(media.retire_watcher());
+    }
+
+    fn settling(settle_ms: u64) -> (SharedMedia, Arc<FakeClock>) {
+        let clock = Arc::new(FakeClock::new());
+        let media = SharedMedia::with_clock(clock.clone());
+        media.lock().settle = Duration::from_millis(settle_ms);
+        (media, clock)
+    }
+
+    #[test]
+    fn bursts_settle_into_one_change() {
+        let (media, clock) = settling(300);
+        let step = Duration::from_millis(100);
+        let mut partial = MediaSnapshot {
+            title: Some("Song".to_string()),
+            ..Default::default()
+        };
+        media.update_state_with(Some(partial.clone()), None);
+        clock.advance(step);
+        partial.artist = Some("Band".to_string());
+        media.update_state_with(Some(partial.clone()), None);
+        clock.advance(step);
+        partial.thumbnail_bytes = Some(vec![1]);
+        media.update_state_with(Some(partial), None);
+
+        // Readers see the new values while the version holds still
+        assert_eq!(media.lock().media.artist.as_deref(), Some("Band"));
+        assert_eq!(media.lock().version, 0);
+        assert_eq!(media.due_in(), Some(Duration::from_millis(300)));
+        clock.advance(Duration::from_millis(299));
+        assert!(!media.run_due());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(media.due_in(), Some(Duration::ZERO));
+        assert!(media.run_due());
+
+        let state = media.lock();
+        assert_eq!(state.version, 1);
+        assert_eq!(
+            state.events.latest().unwrap().to_string(),
+            "1 track title,artist,thumbnail"
+        );
+        assert_eq!(state.updated, Some(clock.now()));
+        drop(state);
+        assert_eq!(media.due_in(), None);
+        assert!(!media.run_due());
+    }
+
+    #[test]
+    fn endless_bursts_still_publish() {
+        let (media, clock) = settling(300);
+        for i in 0..7 {
+            media.update_state_with(Some(track(&format!("Song {i}"), "Band")), None);
+            clock.advance(Duration::from_millis(200));
+        }
+        // Four windows after the burst began, however busy the source still is
+        assert_eq!(media.lock().version, 1);
+    }
+
+    #[test]
+    fn unchanged_updates_do_not_extend_the_window() {
+        let (media, clock) = settling(300);
+        media.update_state_with(Some(track("Song", "Band")), None);
+        clock.advance(Duration::from_millis(200));
+        media.update_state_with(Some(track("Song", "Band")), None);
+        clock.advance(Duration::from_millis(100));
+        assert!(media.run_due());
+    }
+
+    #[test]
+    fn turning_settle_off_publishes_what_was_held() {
+        let (media, _clock) = settling(300);
+        media.update_state_with(Some(track("Song", "Band")), None);
+        media.lock().settle = Duration::ZERO;
+        let playing = session("Spotify.exe", PlaybackStatus::Playing);
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+
+        let state = media.lock();
+        assert_eq!(state.version, 1);
+        assert!(state.pending.is_none());
+        assert_eq!(
+            state.events.latest().unwrap().to_string(),
+            "1 session title,artist,status,source,appid"
+        );
+    }
+
+    fn with_grace(grace_ms: u64) -> (SharedMedia, Arc<FakeClock>) {
+        let clock = Arc::new(FakeClock::new());
+        let media = SharedMedia::with_clock(clock.clone());
+        media.lock().clear_grace = Duration::from_millis(grace_ms);
+        media.update_state_with(Some(track("Song", "Band")), None);
+        (media, clock)
+    }
+
+    fn latest(media: &SharedMedia) -> String {
+        media.lock().events.latest().unwrap().to_string()
+    }
+
+    #[test]
+    fn empty_readings_between_tracks_are_ignored() {
+        let (media, clock) = with_grace(500);
+        media.update_state_with(None, None);
+        let mut untitled = MediaSnapshot {
+            playback_type: Some("Music".to_string()),
+            ..Default::default()
+        };
+        clock.advance(Duration::from_millis(100));
+        media.update_state_with(Some(untitled.clone()), None);
+        assert_eq!(media.lock().version, 1);
+        assert_eq!(media.lock().media.title.as_deref(), Some("Song"));
+
+        clock.advance(Duration::from_millis(100));
+        untitled.title = Some("Next".to_string());
+        media.update_state_with(Some(untitled), None);
+        assert_eq!(latest(&media), "2 track title,artist,playbacktype");
+
+        // The gap is over, so the next one gets a full grace period again
+        clock.advance(Duration::from_millis(400));
+        media.update_state_with(None, None);
+        assert_eq!(media.lock().version, 2);
+        assert_eq!(media.due_in(), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn lasting_empty_readings_clear_once_the_grace_is_over() {
+        let (media, clock) = with_grace(500);
+        media.update_state_with(None, None);
+        clock.advance(Duration::from_millis(499));
+        media.update_state_with(None, None);
+        assert!(!media.run_due());
+        assert_eq!(media.lock().version, 1);
+
+        clock.advance(Duration::from_millis(1));
+        assert!(media.run_due());
+        assert_eq!(latest(&media), "2 cleared title,artist");
+        assert!(media.lock().media.title.is_none());
+        assert_eq!(media.due_in(), None);
+
+        // Nothing left to clear
+        media.update_state_with(None, None);
+        assert_eq!(media.due_in(), None);
+    }
+
+    #[test]
+    fn grace_applies_without_a_timer_too() {
+        let (media, clock) = with_grace(500);
+        media.update_state_with(None, None);
+        clock.advance(Duration::from_millis(500));
+        media.update_state_with(None, None);
+        assert_eq!(latest(&media), "2 cleared title,artist");
+    }
+
+    #[test]
+    fn playback_changes_go_out_during_the_grace_period() {
+        let (media, _clock) = with_grace(500);
+        let playing = session("Spotify.exe", PlaybackStatus::Playing);
+        media.update_state_with(Some(track("Song", "Band")), Some(&playing));
+        let stopped = session("Spotify.exe", PlaybackStatus::Stopped);
+        media.update_state_with(None, Some(&stopped));
+        assert_eq!(latest(&media), "3 status status");
+        assert_eq!(media.lock().media.title.as_deref(), Some("Song"));
+    }
+
+    #[test]
+    fn switching_players_clears_without_grace() {
+        let (media, _clock) = with_grace(500);
+        let spotify = session("Spotify.exe", PlaybackStatus::Playing);
+        media.update_state_with(Some(track("Song", "Band")), Some(&spotify));
+        // The new player has not reported its metadata yet
+        let chrome = session("chrome", PlaybackStatus::Playing);
+        media.update_state_with(None, Some(&chrome));
+        assert_eq!(latest(&media), "3 session title,artist,source,appid");
+        assert!(media.lock().media.title.is_none());
+        assert_eq!(media.due_in(), None);
+    }
+
+    #[test]
+    fn sessions_are_found_by_index_or_app_id() {
+        let sessions = vec![
+            SessionSnapshot {
+                app_id: "Spotify.exe".to_string(),
+                ..Default::default()
+            },
+            SessionSnapshot {
+                app_id: "chrome".to_string(),
+                ..Default::default()
+            },
+        ];
+        let app = |key| find_session(&sessions, key).map(|s| s.app_id.as_str());
+        assert_eq!(app("1"), Some("Spotify.exe"));
+        assert_eq!(app("2"), Some("chrome"));
+        assert_eq!(app("0"), None);
+        assert_eq!(app("3"), None);
+        assert_eq!(app("spotify"), Some("Spotify.exe"));
+        assert_eq!(app("SPOTIFY.EXE"), Some("Spotify.exe"));
+        assert_eq!(app("Chrome"), Some("chrome"));
+        assert_eq!(app("firefox"), None);
+    }
+
+    #[test]
+    fn waiter_wakes_on_change() {
+        let media = leaked();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || tx.send(media.wait_for_change(WaitSpec::default())).unwrap());
+
+        // The waiter must not return until something actually changes
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        media.update_state_with(Some(track("Song", "Band")), None);
+        let Ok(WaitOutcome::Changed(change)) = rx.recv_timeout(Duration::from_secs(5)) else {
+            panic!("waiter was not released by the change");
+        };
+        assert_eq!(change.to_string(), "1 track title,artist");
+    }
+
+    // What each update reports, as the wait_for_media callback would see it
+    fn changes(
+        media: &SharedMedia,
+        updates: &[(Option<MediaSnapshot>, Option<&SessionSnapshot>)],
+    ) -> Vec<String> {
+        updates
+            .iter()
+            .map(|(new, session)| {
+                media.update_state_with(new.clone(), *session);
+                media
+                    .lock()
+                    .events
+                    .latest()
+                    .map(|c| c.to_string())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn changes_are_classified() {
+        let media = SharedMedia::new();
+        let mut spotify = session("Spotify.exe", PlaybackStatus::Playing);
+        let mut enriched = track("Song", "Band");
+        enriched.genres = Some(vec!["Rock".to_string()]);
+        enriched.thumbnail_bytes = Some(vec![1, 2, 3]);
+        let paused = session("Spotify.exe", PlaybackStatus::Paused);
+        let chrome = session("chrome", PlaybackStatus::Playing);
+
+        let first = changes(
+            &media,
+            &[
+                (Some(track("Song", "Band")), Some(&spotify)),
+                (Some(enriched.clone()), Some(&spotify)),
+                (Some(enriched.clone()), Some(&paused)),
+                (Some(track("Next", "Band")), Some(&paused)),
+                (Some(track("Next", "Other")), Some(&paused)),
+                (None, Some(&paused)),
+                (Some(track("Tab", "Site")), Some(&chrome)),
+            ],
+        );
+        assert_eq!(
+            first,
+            [
+                "1 session title,artist,status,source,appid",
+                "2 enriched genres,thumbnail",
+                "3 status status",
+                "4 track title,genres,thumbnail",
+                "5 track artist",
+                "6 cleared title,artist",
+                "7 session title,artist,status,source,appid",
+            ]
+        );
+
+        spotify.app_id = "chrome".to_string();
+        spotify.shuffle = Some(true);
+        spotify.timeline = Some(Timeline {
+            position: Duration::ZERO,
+            duration: Some(Duration::from_secs(100)),
+            updated: SystemTime::UNIX_EPOCH,
+        });
+        let second = changes(
+            &media,
+            &[
+                (Some(track("Tab", "Site")), Some(&spotify)),
+                (Some(track("Tab", "Site")), Some(&chrome)),
+            ],
+        );
+        assert_eq!(
+            second,
+            ["8 enriched duration,shuffle", "9 track duration,shuffle",]
+        );
+    }
+
+    #[test]
+    fn waiter_that_fell_behind_returns_at_once() {
+        let media = SharedMedia::new();
+        for title in ["One", "Two", "Three"] {
+            media.update_state_with(Some(track(title, "Band")), None);
+        }
+        let next = |seen| match media.wait_for_change(WaitSpec {
+            seen: Some(seen),
+            ..Default::default()
+        }) {
+            WaitOutcome::Changed(change) => change.version,
+            outcome => panic!("waiter was released by {outcome:?}"),
+        };
+        assert_eq!(next(0), 1);
+        assert_eq!(next(1), 2);
+        assert_eq!(next(2), 3);
+    }
+
+    #[test]
+    fn filtered_waiter_ignores_other_fields() {
+        let media = leaked();
+        media.update_state_with(Some(track("Song", "Band")), None);
+        let (tx, rx) = mpsc::channel();
+        let spec = WaitSpec::parse("title,artist").unwrap();
+        thread::spawn(move || tx.send(media.wait_for_change(spec)).unwrap());
+
+        let mut enriched = track("Song", "Band");
+        enriched.genres = Some(vec!["Rock".to_string()]);
+        enriched.thumbnail_bytes = Some(vec![1]);
+        media.update_state_with(Some(enriched), None);
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+
+        media.update_state_with(Some(track("Next", "Band")), None);
+        let Ok(WaitOutcome::Changed(change)) = rx.recv_timeout(Duration::from_secs(5)) else {
+            panic!("waiter was not released by the title change");
+        };
+        assert_eq!(change.to_string(), "3 track title,genres,thumbnail");
+    }
+
+    #[test]
+    fn waiter_gives_up_after_its_timeout() {
+        let media = leaked();
+        media.update_state_with(Some(track("Song", "Band")), None);
+        let spec = WaitSpec::parse("timeout=20").unwrap();
+        assert_eq!(media.wait_for_change(spec), WaitOutcome::TimedOut);
+
+        // A change within the timeout releases it as usual
+        let (tx, rx) = mpsc::channel();
+        let spec = WaitSpec::parse("1 timeout=5000").unwrap();
+        thread::spawn(move || tx.send(media.wait_for_change(spec)).unwrap());
+        media.update_state_with(Some(track("Next", "Band")), None);
+        let Ok(WaitOutcome::Changed(change)) = rx.recv_timeout(Duration::from_secs(5)) else {
+            panic!("waiter was not released by the change");
+        };
+        assert_eq!(change.version, 2);
+    }
+
+    #[test]
+    fn halt_releases_waiter_and_stops_listening() {
+        let media = leaked();
+        media.set_listening(true);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || tx.send(media.wait_for_change(WaitSpec::default())).unwrap());
+
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        media.halt();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(WaitOutcome::Cancelled)
+        );
+        assert!(!media.is_listening());
+    }
+
+    #[test]
+    fn listeners_keep_their_own_place() {
+        let media = SharedMedia::new();
+        let next = |spec: &str| match media.wait_for_change(WaitSpec::parse(spec).unwrap()) {
+            WaitOutcome::Changed(change) => change.version,
+            outcome => panic!("waiter was released by {outcome:?}"),
+        };
+        let timeout = |spec: &str| media.wait_for_change(WaitSpec::parse(spec).unwrap());
+
+        // Both register at version 0 and then fall behind by two changes
+        assert_eq!(timeout("id=one timeout=0"), WaitOutcome::TimedOut);
+        assert_eq!(timeout("id=two timeout=0"), WaitOutcome::TimedOut);
+        media.update_state_with(Some(track("One", "Band")), None);
+        media.update_state_with(Some(track("Two", "Band")), None);
+
+        assert_eq!(next("id=one"), 1);
+        assert_eq!(next("id=one"), 2);
+        assert_eq!(next("id=two"), 1);
+        assert_eq!(timeout("id=one timeout=0"), WaitOutcome::TimedOut);
+        assert_eq!(next("id=two"), 2);
+        // Anonymous callers still start from the current version
+        assert_eq!(timeout("timeout=0"), WaitOutcome::TimedOut);
+    }
+
+    #[test]
+    fn halting_one_listener_leaves_the_others_waiting() {
+        let media = leaked();
+        media.set_listening(true);
+        let (tx, rx) = mpsc::channel();
+        for id in ["one", "two"] {
+            let tx = tx.clone();
+            let spec = WaitSpec::parse(&format!("id={id}")).unwrap();
+            thread::spawn(move || tx.send((id, media.wait_for_change(spec))).unwrap());
+        }
+        while media.lock().listeners.len() < 2 {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(!media.halt_listener("nobody"));
+        assert!(media.halt_listener("ONE"));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(("one", WaitOutcome::Cancelled))
+        );
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        assert!(media.is_listening());
+
+        media.update_state_with(Some(track("Song", "Band")), None);
+        let Ok(("two", WaitOutcome::Changed(change))) = rx.recv_timeout(Duration::from_secs(5))
+        else {
+            panic!("the other listener was not released by the change");
+        };
+        assert_eq!(change.version, 1);
+
+        // Halting the last one left stops listening altogether
+        assert!(media.halt_listener("two"));
+        assert!(!media.is_listening());
+        assert!(media.lock().listeners.is_empty());
+    }
+
+    #[test]
+    fn new_listeners_start_from_the_current_version() {
+        let media = SharedMedia::new();
+        for title in ["One", "Two", "Three"] {
+            media.update_state_with(Some(track(title, "Band")), None);
+        }
+        let spec = WaitSpec::parse("id=fresh timeout=20").unwrap();
+        assert_eq!(media.wait_for_change(spec), WaitOutcome::TimedOut);
+
+        // Once halted, the same id registers afresh
+        media.update_state_with(Some(track("Four", "Band")), None);
+        assert!(media.halt_listener("fresh"));
+        let spec = WaitSpec::parse("id=fresh timeout=20").unwrap();
+        assert_eq!(media.wait_for_change(spec), WaitOutcome::TimedOut);
+    }
+}
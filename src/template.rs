@@ -0,0 +1,820 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{CharIndices, Chars};
+use std::time::SystemTime;
+
+use crate::field::{Field, Value};
+use crate::policy::parse_switch;
+use crate::state::MediaState;
+use crate::timeline::clock_text;
+
+/// Settings shared by every template the `format` export renders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FormatOptions {
+    pub(crate) genre_separator: String,
+    // Leave out every control code, for channels with +c
+    pub(crate) strip: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            genre_separator: ", ".to_string(),
+            strip: false,
+        }
+    }
+}
+
+impl FormatOptions {
+    /// Sets one option from `key=value`, taking the value verbatim, spaces included.
+    /// A bare `key` leaves the option alone. Returns the option's value.
+    pub(crate) fn apply(&mut self, spec: &str) -> Result<String, String> {
+        let (key, value) = match spec.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (spec, None),
+        };
+        match key.trim().to_ascii_lowercase().as_str() {
+            "genresep" => {
+                if let Some(value) = value {
+                    self.genre_separator = value.to_string();
+                }
+                Ok(self.genre_separator.clone())
+            }
+            "strip" => {
+                if let Some(value) = value {
+                    self.strip = parse_switch(value.trim())?;
+                }
+                Ok(Value::Switch(self.strip).to_string())
+            }
+            _ => Err(format!("unknown format option '{}'", key.trim())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Left,
+    Right,
+}
+
+// Widths and maximums beyond mIRC's largest return buffer can only be mistakes
+const MAX_WIDTH: usize = 8192;
+
+// `:[<|>][0][width][.max][s]`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Spec {
+    align: Option<Align>,
+    zero: bool,
+    width: usize,
+    max: Option<usize>,
+    // Time fields as whole seconds instead of m:ss
+    seconds: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Alternative {
+    Field(Field),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Placeholder {
+    alternatives: Vec<Alternative>,
+    spec: Spec,
+}
+
+// mIRC colour numbers by name; 16 to 98 are only reachable by number
+const COLORS: &[(&str, u8)] = &[
+    ("white", 0),
+    ("black", 1),
+    ("navy", 2),
+    ("blue", 2),
+    ("green", 3),
+    ("red", 4),
+    ("maroon", 5),
+    ("brown", 5),
+    ("purple", 6),
+    ("orange", 7),
+    ("olive", 7),
+    ("yellow", 8),
+    ("lime", 9),
+    ("lightgreen", 9),
+    ("teal", 10),
+    ("cyan", 10),
+    ("aqua", 11),
+    ("lightcyan", 11),
+    ("royal", 12),
+    ("lightblue", 12),
+    ("pink", 13),
+    ("fuchsia", 13),
+    ("grey", 14),
+    ("gray", 14),
+    ("silver", 15),
+    ("lightgrey", 15),
+    ("lightgray", 15),
+    ("default", 99),
+];
+
+/// A mIRC text style written as `{bold}`, `{color(red,black)}` and so on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Bold,
+    Italic,
+    Underline,
+    Reverse,
+    // `{color}` on its own ends colouring
+    Color { fg: Option<u8>, bg: Option<u8> },
+    Reset,
+}
+
+impl Style {
+    // None when `name` is not a style at all
+    fn parse(name: &str) -> Option<Result<Style, String>> {
+        let name = name.trim().to_ascii_lowercase();
+        let style = match name.as_str() {
+            "bold" => Style::Bold,
+            "italic" => Style::Italic,
+            "underline" => Style::Underline,
+            "reverse" => Style::Reverse,
+            "reset" => Style::Reset,
+            "color" | "color()" => Style::Color { fg: None, bg: None },
+            _ => {
+                let args = name.strip_prefix("color(")?.strip_suffix(')')?;
+                let (fg, bg) = match args.split_once(',') {
+                    Some((fg, bg)) => (fg, Some(bg)),
+                    None => (args, None),
+                };
+                return Some(color(fg).and_then(|fg| {
+                    let bg = bg.map(color).transpose()?;
+                    Ok(Style::Color { fg: Some(fg), bg })
+                }));
+            }
+        };
+        Some(Ok(style))
+    }
+
+    fn write(self, out: &mut String) {
+        match self {
+            Style::Bold => out.push('\x02'),
+            Style::Italic => out.push('\x1D'),
+            Style::Underline => out.push('\x1F'),
+            Style::Reverse => out.push('\x16'),
+            Style::Reset => out.push('\x0F'),
+            Style::Color { fg, bg } => {
+                out.push('\x03');
+                // Always two digits, so a number right after the code isn't read as part of it
+                if let Some(fg) = fg {
+                    out.push_str(&format!("{fg:02}"));
+                }
+                if let Some(bg) = bg {
+                    out.push_str(&format!(",{bg:02}"));
+                }
+            }
+        }
+    }
+}
+
+// A colour by name or number (0-99)
+fn color(value: &str) -> Result<u8, String> {
+    let value = value.trim();
+    COLORS
+        .iter()
+        .find(|(name, _)| *name == value)
+        .map(|&(_, n)| n)
+        .or_else(|| value.parse().ok().filter(|&n| n <= 99))
+        .ok_or_else(|| format!("unknown color '{value}'"))
+}
+
+/// Removes mIRC formatting: bold, italic, underline, strikethrough, monospace,
+/// reverse, reset and colour codes along with their colour numbers.
+pub(crate) fn strip_codes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x02' | '\x1D' | '\x1F' | '\x1E' | '\x11' | '\x16' | '\x0F' => {}
+            '\x03' => {
+                // The comma only belongs to the code when a background follows it
+                if skip_color(&mut chars) && chars.peek() == Some(&',') {
+                    let mut ahead = chars.clone();
+                    ahead.next();
+                    if skip_color(&mut ahead) {
+                        chars = ahead;
+                    }
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Skips a colour number of up to two digits; false when there was none
+fn skip_color(chars: &mut Peekable<Chars<'_>>) -> bool {
+    let mut found = false;
+    for _ in 0..2 {
+        found |= chars.next_if(char::is_ascii_digit).is_some();
+    }
+    found
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Placeholder(Placeholder),
+    Style(Style),
+    // Dropped entirely when one of its placeholders comes out empty
+    Section(Vec<Node>),
+}
+
+/// Where and why a template failed to parse. Positions count characters from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TemplateError {
+    position: usize,
+    message: String,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+/// A parsed `format` template.
+///
+/// - `{field}` is replaced by the field's value; names match the accessor exports.
+/// - `{albumartist|artist|"Unknown"}` takes the first alternative that is not empty.
+/// - `{tracknumber:02}` pads or truncates, see `Spec`.
+/// - `[...]` is a section, left out when any placeholder directly inside it is empty.
+/// - `{bold}`, `{color(fg,bg)}` and the other `Style`s insert mIRC control codes.
+/// - `\` takes the next character literally.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub(crate) fn parse(source: &str) -> Result<Template, TemplateError> {
+        let mut parser = Parser {
+            chars: source.char_indices().peekable(),
+            source,
+        };
+        Ok(Template {
+            nodes: parser.nodes(None)?,
+        })
+    }
+
+    pub(crate) fn render(
+        &self,
+        state: &MediaState,
+        now: SystemTime,
+        options: &FormatOptions,
+    ) -> String {
+        let mut out = String::new();
+        render_nodes(
+            &self.nodes,
+            &Context {
+                state,
+                now,
+                options,
+            },
+            &mut out,
+        );
+        // Codes can also come from the template text or the track itself
+        if options.strip {
+            out = strip_codes(&out);
+        }
+        out
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    source: &'a str,
+}
+
+impl Parser<'_> {
+    fn error(&self, byte: usize, message: impl Into<String>) -> TemplateError {
+        TemplateError {
+            position: self.source[..byte].chars().count() + 1,
+            message: message.into(),
+        }
+    }
+
+    // Parses up to the `]` closing the section opened at `open`, or to the end
+    // of the template at the top level
+    fn nodes(&mut self, open: Option<usize>) -> Result<Vec<Node>, TemplateError> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        let flush = |text: &mut String, nodes: &mut Vec<Node>| {
+            if !text.is_empty() {
+                nodes.push(Node::Text(std::mem::take(text)));
+            }
+        };
+
+        while let Some((pos, c)) = self.chars.next() {
+            match c {
+                // A trailing backslash stays as it is
+                '\\' => text.push(self.chars.next().map_or('\\', |(_, c)| c)),
+                '{' => {
+                    flush(&mut text, &mut nodes);
+                    nodes.push(self.placeholder(pos)?);
+                }
+                '[' => {
+                    flush(&mut text, &mut nodes);
+                    nodes.push(Node::Section(self.nodes(Some(pos))?));
+                }
+                ']' if open.is_some() => {
+                    flush(&mut text, &mut nodes);
+                    return Ok(nodes);
+                }
+                ']' => return Err(self.error(pos, "unexpected ']'")),
+                '}' => return Err(self.error(pos, "unexpected '}'")),
+                c => text.push(c),
+            }
+        }
+
+        if let Some(open) = open {
+            return Err(self.error(open, "unclosed '['"));
+        }
+        flush(&mut text, &mut nodes);
+        Ok(nodes)
+    }
+
+    // Parses the rest of a placeholder or style whose `{` is at `open`
+    fn placeholder(&mut self, open: usize) -> Result<Node, TemplateError> {
+        let mut alternatives = Vec::new();
+        let mut name = String::new();
+        let mut name_pos = None;
+        let mut literal = None;
+
+        loop {
+            let Some((pos, c)) = self.chars.next() else {
+                return Err(self.error(open, "unclosed '{'"));
+            };
+            match c {
+                '"' if literal.is_none() && name.trim().is_empty() => {
+                    literal = Some(self.literal(pos)?);
+                }
+                '"' => return Err(self.error(pos, "unexpected '\"'")),
+                '|' | ':' | '}' => {
+                    alternatives.push(match literal.take() {
+                        Some(text) if name.trim().is_empty() => Alternative::Literal(text),
+                        Some(_) => {
+                            return Err(self
+                                .error(name_pos.unwrap_or(pos), "unexpected text after literal"));
+                        }
+                        None => {
+                            let at = name_pos.unwrap_or(pos);
+                            if name.trim().is_empty() {
+                                return Err(self.error(at, "missing field name"));
+                            }
+                            // Styles stand alone, without fallbacks or a spec
+                            if alternatives.is_empty()
+                                && c == '}'
+                                && let Some(style) = Style::parse(&name)
+                            {
+                                return style.map(Node::Style).map_err(|err| self.error(at, err));
+                            }
+                            let field = Field::parse(&name).ok_or_else(|| {
+                                self.error(at, format!("unknown field '{}'", name.trim()))
+                            })?;
+                            Alternative::Field(field)
+                        }
+                    });
+                    name.clear();
+                    name_pos = None;
+                    match c {
+                        ':' => {
+                            return Ok(Node::Placeholder(Placeholder {
+                                alternatives,
+                                spec: self.spec(pos + 1, open)?,
+                            }));
+                        }
+                        '}' => {
+                            return Ok(Node::Placeholder(Placeholder {
+                                alternatives,
+                                spec: Spec::default(),
+                            }));
+                        }
+                        _ => {}
+                    }
+                }
+                c => {
+                    if !c.is_whitespace() && name_pos.is_none() {
+                        name_pos = Some(pos);
+                    }
+                    name.push(c);
+                }
+            }
+        }
+    }
+
+    // A quoted fallback; `\"` and `\\` escape inside it
+    fn literal(&mut self, open: usize) -> Result<String, TemplateError> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(text),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, c)) => text.push(c),
+                    None => break,
+                },
+                Some((_, c)) => text.push(c),
+                None => break,
+            }
+        }
+        Err(self.error(open, "unclosed '\"'"))
+    }
+
+    // The spec runs from `start` to the placeholder's closing brace
+    fn spec(&mut self, start: usize, open: usize) -> Result<Spec, TemplateError> {
+        let mut raw = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '}')) => break,
+                Some((_, c)) => raw.push(c),
+                None => return Err(self.error(open, "unclosed '{'")),
+            }
+        }
+
+        let bad = || self.error(start, format!("bad format spec '{raw}'"));
+        let mut rest = raw.trim();
+        let mut spec = Spec::default();
+        if let Some(r) = rest.strip_prefix('<') {
+            spec.align = Some(Align::Left);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('>') {
+            spec.align = Some(Align::Right);
+            rest = r;
+        }
+        if let Some(r) = rest.strip_prefix('0') {
+            spec.zero = true;
+            rest = r;
+        }
+        if let Some(r) = rest.strip_suffix('s') {
+            spec.seconds = true;
+            rest = r;
+        }
+        let (width, max) = match rest.split_once('.') {
+            Some((width, max)) => (width, Some(max)),
+            None => (rest, None),
+        };
+        let number = |text: &str| match text.parse() {
+            Ok(n) if n <= MAX_WIDTH => Ok(n),
+            _ => Err(bad()),
+        };
+        if !width.is_empty() {
+            spec.width = number(width)?;
+        }
+        if let Some(max) = max {
+            spec.max = Some(number(max)?);
+        }
+        Ok(spec)
+    }
+}
+
+struct Context<'a> {
+    state: &'a MediaState,
+    now: SystemTime,
+    options: &'a FormatOptions,
+}
+
+// Appends `nodes` to `out`; false when one of the placeholders among them came out empty
+fn render_nodes(nodes: &[Node], ctx: &Context<'_>, out: &mut String) -> bool {
+    let mut complete = true;
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Style(style) => style.write(out),
+            Node::Placeholder(placeholder) => match render_placeholder(placeholder, ctx) {
+                Some(text) => out.push_str(&text),
+                None => complete = false,
+            },
+            Node::Section(inner) => {
+                let mut section = String::new();
+                if render_nodes(inner, ctx, &mut section) {
+                    out.push_str(&section);
+                }
+            }
+        }
+    }
+    complete
+}
+
+fn render_placeholder(placeholder: &Placeholder, ctx: &Context<'_>) -> Option<String> {
+    let spec = placeholder.spec;
+    let (text, numeric) = placeholder.alternatives.iter().find_map(|alt| match alt {
+        Alternative::Literal(text) => (!text.is_empty()).then(|| (text.clone(), false)),
+        Alternative::Field(field) => show(&field.read(ctx.state, ctx.now), spec, ctx.options),
+    })?;
+    Some(pad(text, spec, numeric))
+}
+
+// A value as templates show it, and whether it lines up like a number
+fn show(value: &Value, spec: Spec, options: &FormatOptions) -> Option<(String, bool)> {
+    match value {
+        Value::Empty => None,
+        Value::Text(text) => Some((text.clone(), false)),
+        Value::List(items) => Some((items.join(&options.genre_separator), false)),
+        Value::Number(n) => Some((n.to_string(), true)),
+        Value::Decimal(x) => Some((x.to_string(), true)),
+        Value::Time(time) if spec.seconds => Some((time.as_secs().to_string(), true)),
+        Value::Time(time) => Some((clock_text(*time), true)),
+        Value::Switch(on) => Some((if *on { "on" } else { "off" }.to_string(), false)),
+    }
+}
+
+fn pad(mut text: String, spec: Spec, numeric: bool) -> String {
+    if let Some(max) = spec.max
+        && let Some((cut, _)) = text.char_indices().nth(max)
+    {
+        text.truncate(cut);
+    }
+    let len = text.chars().count();
+    if len >= spec.width {
+        return text;
+    }
+    let fill = spec.width - len;
+    // Zero padding only makes sense in front; numbers line up to the right by default
+    let align = match spec.align {
+        _ if spec.zero => Align::Right,
+        Some(align) => align,
+        None if numeric => Align::Right,
+        None => Align::Left,
+    };
+    let padding: String = std::iter::repeat_n(if spec.zero { '0' } else { ' ' }, fill).collect();
+    match align {
+        Align::Left => text + &padding,
+        Align::Right => padding + &text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::state::PlaybackStatus;
+    use crate::timeline::Timeline;
+
+    fn now() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)
+    }
+
+    fn state() -> MediaState {
+        let mut state = MediaState {
+            status: Some(PlaybackStatus::Paused),
+            timeline: Some(Timeline {
+                position: Duration::from_secs(83),
+                duration: Some(Duration::from_secs(3725)),
+                updated: now(),
+            }),
+            app_id: Some("Spotify.exe".to_string()),
+            ..Default::default()
+        };
+        state.media.title = Some("Song".to_string());
+        state.media.artist = Some("Band".to_string());
+        state.media.track_number = Some(7);
+        state.media.genres = Some(vec!["Rock".to_string(), "Pop".to_string()]);
+        state
+    }
+
+    fn render(template: &str) -> String {
+        render_with(template, &FormatOptions::default())
+    }
+
+    fn render_with(template: &str, options: &FormatOptions) -> String {
+        Template::parse(template)
+            .unwrap()
+            .render(&state(), now(), options)
+    }
+
+    fn error(template: &str) -> String {
+        Template::parse(template).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn plain_text_passes_through() {
+        assert_eq!(render(""), "");
+        assert_eq!(render("np: nothing"), "np: nothing");
+        assert_eq!(render("ünïcode ♪"), "ünïcode ♪");
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        assert_eq!(render("{artist} - {title}"), "Band - Song");
+        assert_eq!(render("{ Title }"), "Song");
+        assert_eq!(render("({status})"), "(paused)");
+        assert_eq!(render("{source} ({appid})"), "Spotify (Spotify.exe)");
+        assert_eq!(render("{albumtitle}"), "");
+    }
+
+    #[test]
+    fn missing_fields_render_empty() {
+        assert_eq!(render("{title}{albumtitle}!"), "Song!");
+    }
+
+    #[test]
+    fn sections_drop_when_a_placeholder_is_empty() {
+        assert_eq!(render("{title}[ from {albumtitle}]"), "Song");
+        assert_eq!(render("{title}[ by {artist}]"), "Song by Band");
+        // Every placeholder in the section has to have a value
+        assert_eq!(render("[#{tracknumber}/{albumtrackcount}]"), "");
+        assert_eq!(render("[#{tracknumber}]"), "#7");
+        // Sections without placeholders always show
+        assert_eq!(render("[plain]"), "plain");
+    }
+
+    #[test]
+    fn nested_sections_are_independent() {
+        assert_eq!(render("[{title}[ ({albumtitle})]]"), "Song");
+        assert_eq!(render("[{albumtitle}[ ({title})]]"), "");
+        assert_eq!(
+            render("[{artist}[ - {title}[ ({subtitle})]]]"),
+            "Band - Song"
+        );
+    }
+
+    #[test]
+    fn fallbacks_take_the_first_value() {
+        assert_eq!(render("{albumartist|artist}"), "Band");
+        assert_eq!(render("{title|artist}"), "Song");
+        assert_eq!(render("{albumtitle|\"Unknown album\"}"), "Unknown album");
+        assert_eq!(render("{\"literal\"|title}"), "literal");
+        assert_eq!(render("{albumtitle|subtitle}"), "");
+        // An empty literal is no value either
+        assert_eq!(render("[x{albumtitle|\"\"}]"), "");
+        // A fallback that fills in keeps the section
+        assert_eq!(render("[by {albumartist|artist}]"), "by Band");
+    }
+
+    #[test]
+    fn literals_allow_escapes_and_special_characters() {
+        assert_eq!(render("{albumtitle|\"a|b:c}\"}"), "a|b:c}");
+        assert_eq!(render("{albumtitle|\"say \\\"hi\\\"\"}"), "say \"hi\"");
+    }
+
+    #[test]
+    fn backslash_escapes_markup() {
+        assert_eq!(render("\\{title\\}"), "{title}");
+        assert_eq!(render("\\[{title}\\]"), "[Song]");
+        assert_eq!(render("a\\\\b"), "a\\b");
+        assert_eq!(render("trailing\\"), "trailing\\");
+    }
+
+    #[test]
+    fn numbers_can_be_padded() {
+        assert_eq!(render("{tracknumber:02}"), "07");
+        assert_eq!(render("{tracknumber:03}"), "007");
+        assert_eq!(render("{tracknumber:3}"), "  7");
+        assert_eq!(render("{tracknumber:<3}|"), "7  |");
+        assert_eq!(render("{tracknumber:01}"), "7");
+    }
+
+    #[test]
+    fn text_can_be_padded_and_truncated() {
+        assert_eq!(render("{title:6}|"), "Song  |");
+        assert_eq!(render("{title:>6}"), "  Song");
+        assert_eq!(render("{title:.2}"), "So");
+        assert_eq!(render("{title:6.2}|"), "So    |");
+        assert_eq!(render("{title:.10}"), "Song");
+    }
+
+    #[test]
+    fn spec_applies_to_the_chosen_fallback() {
+        assert_eq!(render("{albumartist|artist:>6}"), "  Band");
+        assert_eq!(render("{albumtitle|\"?\":03}"), "00?");
+    }
+
+    #[test]
+    fn times_show_as_clock_or_seconds() {
+        assert_eq!(render("{position}/{duration}"), "1:23/1:02:05");
+        assert_eq!(render("{position:s}"), "83");
+        assert_eq!(render("{remaining:s}"), "3642");
+        assert_eq!(render("{position:6}"), "  1:23");
+    }
+
+    #[test]
+    fn genre_separator_is_configurable() {
+        assert_eq!(render("{genres}"), "Rock, Pop");
+        let mut options = FormatOptions::default();
+        assert_eq!(options.apply("genresep= / "), Ok(" / ".to_string()));
+        assert_eq!(render_with("{genres}", &options), "Rock / Pop");
+        assert_eq!(options.apply("strip= on"), Ok("on".to_string()));
+        assert!(options.strip);
+    }
+
+    #[test]
+    fn options_read_back_and_reject_unknown_keys() {
+        let mut options = FormatOptions::default();
+        assert!(options.apply("separator=;").is_err());
+        assert!(options.apply("").is_err());
+        assert!(options.apply("strip=maybe").is_err());
+        assert_eq!(options.apply("GenreSep"), Ok(", ".to_string()));
+        assert_eq!(options, FormatOptions::default());
+    }
+
+    #[test]
+    fn styles_expand_to_control_codes() {
+        assert_eq!(render("{bold}{title}{bold}"), "\x02Song\x02");
+        assert_eq!(
+            render("{italic}{underline}{reverse}{reset}"),
+            "\x1D\x1F\x16\x0F"
+        );
+        assert_eq!(render("{color(4)}{tracknumber}"), "\x03047");
+        assert_eq!(render("{color(red,black)}x{color}"), "\x0304,01x\x03");
+        assert_eq!(render("{ Color( 12 , 99 ) }"), "\x0312,99");
+        assert_eq!(render("{color()}"), "\x03");
+    }
+
+    #[test]
+    fn styles_do_not_empty_sections() {
+        assert_eq!(render("[{bold}]"), "\x02");
+        assert_eq!(render("[{bold}{albumtitle}{bold}]"), "");
+        assert_eq!(render("[{bold}{title}{bold}]"), "\x02Song\x02");
+    }
+
+    #[test]
+    fn strip_mode_removes_every_code() {
+        let options = FormatOptions {
+            strip: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            render_with("{bold}{color(4,1)}{title}{reset} \x0312,1x \x1Ey", &options),
+            "Song x y"
+        );
+    }
+
+    #[test]
+    fn strip_codes_keeps_text_after_colors() {
+        assert_eq!(strip_codes("\x03047"), "7");
+        assert_eq!(strip_codes("\x034,5x"), "x");
+        assert_eq!(strip_codes("\x034,x"), ",x");
+        assert_eq!(strip_codes("\x03,4"), ",4");
+        assert_eq!(strip_codes("a\x02b\x0Fc"), "abc");
+        assert_eq!(strip_codes("plain ♪"), "plain ♪");
+    }
+
+    #[test]
+    fn malformed_templates_report_where() {
+        assert_eq!(error("{title"), "unclosed '{' at position 1");
+        assert_eq!(error("ab[{title}"), "unclosed '[' at position 3");
+        assert_eq!(error("{title}]"), "unexpected ']' at position 8");
+        assert_eq!(error("x}"), "unexpected '}' at position 2");
+        assert_eq!(error("{nope}"), "unknown field 'nope' at position 2");
+        assert_eq!(error("{title|}"), "missing field name at position 8");
+        assert_eq!(error("{}"), "missing field name at position 2");
+        assert_eq!(error("{title:x}"), "bad format spec 'x' at position 8");
+        assert_eq!(error("{title:2.}"), "bad format spec '2.' at position 8");
+        assert_eq!(
+            error("{title:99999999999}"),
+            "bad format spec '99999999999' at position 8"
+        );
+        assert_eq!(
+            error("{title:.8193}"),
+            "bad format spec '.8193' at position 8"
+        );
+        assert_eq!(error("{\"open}"), "unclosed '\"' at position 2");
+        assert_eq!(
+            error("{\"a\"title}"),
+            "unexpected text after literal at position 5"
+        );
+        assert_eq!(error("{ti\"t\"}"), "unexpected '\"' at position 4");
+        assert_eq!(error("ü{nope}"), "unknown field 'nope' at position 3");
+        assert_eq!(
+            error("{color(mauve)}"),
+            "unknown color 'mauve' at position 2"
+        );
+        assert_eq!(error("{color(100)}"), "unknown color '100' at position 2");
+        assert_eq!(error("{color(,4)}"), "unknown color '' at position 2");
+        assert_eq!(error("{bold|title}"), "unknown field 'bold' at position 2");
+        assert_eq!(error("{bold:2}"), "unknown field 'bold' at position 2");
+    }
+
+    #[test]
+    fn parsed_structure() {
+        let template = Template::parse("a[{title|\"x\":<04.2s}]").unwrap();
+        assert_eq!(
+            template.nodes,
+            vec![
+                Node::Text("a".to_string()),
+                Node::Section(vec![Node::Placeholder(Placeholder {
+                    alternatives: vec![
+                        Alternative::Field(Field::Title),
+                        Alternative::Literal("x".to_string()),
+                    ],
+                    spec: Spec {
+                        align: Some(Align::Left),
+                        zero: true,
+                        width: 4,
+                        max: Some(2),
+                        seconds: true,
+                    },
+                })]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use std::time::{Duration, SystemTime};
+
+/// Where playback was when the source last reported it. Sources only report the
+/// timeline now and then, so readers extrapolate from `updated` while playing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Timeline {
+    pub(crate) position: Duration,
+    pub(crate) duration: Option<Duration>,
+    // When `position` was sampled
+    pub(crate) updated: SystemTime,
+}
+
+impl Timeline {
+    /// The position at `now`, advanced by the time since the last update scaled
+    /// by `speed` (zero while not playing) and never past the end of the track.
+    pub(crate) fn position_at(&self, now: SystemTime, speed: f64) -> Duration {
+        let mut position = self.position;
+        if speed > 0.0 && speed.is_finite() {
+            // A clock that went backwards extrapolates nothing
+            let elapsed = now.duration_since(self.updated).unwrap_or_default();
+            position = position.saturating_add(elapsed.mul_f64(speed));
+        }
+        match self.duration {
+            Some(duration) => position.min(duration),
+            None => position,
+        }
+    }
+
+    pub(crate) fn remaining_at(&self, now: SystemTime, speed: f64) -> Option<Duration> {
+        Some(self.duration?.saturating_sub(self.position_at(now, speed)))
+    }
+}
+
+/// Where the current time comes from; tests swap in a clock they can move by hand.
+pub(crate) trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+// m:ss, or h:mm:ss from an hour up
+pub(crate) fn clock_text(time: Duration) -> String {
+    let secs = time.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{h}:{m:02}:{s:02}")
+    } else {
+        format!("{m}:{s:02}")
+    }
+}
+
+// Whole seconds followed by the clock form, e.g. "83 1:23"
+pub(crate) fn time_text(time: Duration) -> String {
+    format!("{} {}", time.as_secs(), clock_text(time))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    pub(crate) struct FakeClock(Mutex<SystemTime>);
+
+    impl FakeClock {
+        pub(crate) fn new() -> Self {
+            FakeClock(Mutex::new(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000),
+            ))
+        }
+
+        pub(crate) fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn at(position: u64, duration: Option<u64>, clock: &FakeClock) -> Timeline {
+        Timeline {
+            position: Duration::from_secs(position),
+            duration: duration.map(Duration::from_secs),
+            updated: clock.now(),
+        }
+    }
+
+    #[test]
+    fn position_advances_only_while_playing() {
+        let clock = FakeClock::new();
+        let timeline = at(30, Some(200), &clock);
+        clock.advance(Duration::from_millis(12_500));
+
+        assert_eq!(
+            timeline.position_at(clock.now(), 1.0),
+            Duration::from_millis(42_500)
+        );
+        assert_eq!(
+            timeline.position_at(clock.now(), 0.0),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            timeline.remaining_at(clock.now(), 1.0),
+            Some(Duration::from_millis(157_500))
+        );
+    }
+
+    #[test]
+    fn position_follows_playback_rate() {
+        let clock = FakeClock::new();
+        let timeline = at(30, Some(200), &clock);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(
+            timeline.position_at(clock.now(), 1.5),
+            Duration::from_secs(45)
+        );
+        assert_eq!(
+            timeline.position_at(clock.now(), -1.0),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn position_is_clamped_to_duration() {
+        let clock = FakeClock::new();
+        let timeline = at(190, Some(200), &clock);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(
+            timeline.position_at(clock.now(), 1.0),
+            Duration::from_secs(200)
+        );
+        assert_eq!(
+            timeline.remaining_at(clock.now(), 1.0),
+            Some(Duration::ZERO)
+        );
+
+        // Streams without a known length keep counting and have no remaining time
+        let live = at(190, None, &clock);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(live.position_at(clock.now(), 1.0), Duration::from_secs(250));
+        assert_eq!(live.remaining_at(clock.now(), 1.0), None);
+    }
+
+    #[test]
+    fn update_from_the_future_does_not_extrapolate() {
+        let clock = FakeClock::new();
+        let timeline = at(30, Some(200), &clock);
+        let earlier = clock.now() - Duration::from_secs(5);
+        assert_eq!(timeline.position_at(earlier, 1.0), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn time_text_has_seconds_and_clock() {
+        assert_eq!(time_text(Duration::ZERO), "0 0:00");
+        assert_eq!(time_text(Duration::from_millis(83_900)), "83 1:23");
+        assert_eq!(time_text(Duration::from_secs(3723)), "3723 1:02:03");
+    }
+}
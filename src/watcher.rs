@@ -0,0 +1,744 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use debug_print::debug_eprintln;
+
+use crate::policy::Selection;
+use crate::source::{MediaSource, SourceError, SourceEvent};
+use crate::state::SharedMedia;
+
+// Pause before starting a failed source again; doubles with every failure in a row
+#[cfg(not(test))]
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+#[cfg(test)]
+const FIRST_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = FIRST_BACKOFF.saturating_mul(60);
+
+/// What the watcher thread is doing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum WatcherState {
+    #[default]
+    Stopped,
+    Starting,
+    Running,
+    /// The source failed; it is started again after a pause.
+    Retrying,
+}
+
+impl WatcherState {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            WatcherState::Stopped => "stopped",
+            WatcherState::Starting => "starting",
+            WatcherState::Running => "running",
+            WatcherState::Retrying => "retrying",
+        }
+    }
+}
+
+/// How the watcher has fared, for the health export.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Health {
+    pub(crate) state: WatcherState,
+    // The source's name, once the watcher has one
+    pub(crate) backend: Option<&'static str>,
+    // How often the source was started again after failing
+    pub(crate) restarts: u32,
+    // Kept after the source recovers
+    pub(crate) last_error: Option<String>,
+}
+
+// "<state> <restarts> [<last error>]", as the health export returns it
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.state.as_str(), self.restarts)?;
+        if let Some(ref err) = self.last_error {
+            write!(f, " {err}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(windows, target_os = "linux"))]
+static MEDIA_WATCHER: WatcherSlot = WatcherSlot::new();
+
+/// Starts the platform's media watcher unless it is already running.
+#[cfg(any(windows, target_os = "linux"))]
+pub(crate) fn start_media_watcher() {
+    MEDIA_WATCHER.start(crate::state::ensure_state(), crate::source::platform_source);
+}
+
+/// Stops the platform's media watcher and waits for its thread to end.
+#[cfg(any(windows, target_os = "linux"))]
+pub(crate) fn stop_media_watcher() {
+    MEDIA_WATCHER.stop(crate::state::ensure_state());
+}
+
+/// Holds the one watcher thread so it can be stopped and started again.
+pub(crate) struct WatcherSlot {
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WatcherSlot {
+    pub(crate) const fn new() -> Self {
+        WatcherSlot {
+            thread: Mutex::new(None),
+        }
+    }
+
+    /// Spawns a watcher over the source `make` builds, unless one is running.
+    /// One that retired for being idle may still be on its way out; it is waited
+    /// for and replaced.
+    pub(crate) fn start<S: MediaSource>(
+        &self,
+        media: &'static SharedMedia,
+        make: impl FnOnce() -> S,
+    ) {
+        let mut thread = self.thread.lock().unwrap();
+        if thread.as_ref().is_some_and(|t| !t.is_finished()) && media.has_watcher() {
+            return;
+        }
+        if let Some(watcher) = thread.take()
+            && watcher.join().is_err()
+        {
+            debug_eprintln!("m_nowplaying: media watcher panicked");
+        }
+        *thread = Some(spawn_watcher(make(), media));
+    }
+
+    /// Tells the watcher to stop, which unsubscribes the source, and waits for
+    /// its thread. Starting is held off until it is gone.
+    pub(crate) fn stop(&self, media: &SharedMedia) {
+        let mut thread = self.thread.lock().unwrap();
+        let Some(watcher) = thread.take() else {
+            return;
+        };
+        media.notify_watcher(SourceEvent::Shutdown);
+        media.detach_watcher();
+        if watcher.join().is_err() {
+            debug_eprintln!("m_nowplaying: media watcher panicked");
+        }
+    }
+}
+
+pub(crate) fn spawn_watcher<S: MediaSource>(
+    source: S,
+    media: &'static SharedMedia,
+) -> JoinHandle<()> {
+    // Attach before the thread runs so commands sent right away are queued, not refused
+    let (tx, rx) = mpsc::channel();
+    media.attach_watcher(tx.clone());
+    thread::spawn(move || run_watcher(source, media, tx, rx))
+}
+
+// Drives `source` until it is told to shut down or its event stream closes, folding
+// every notification into `media`. A source that fails to start or is lost is
+// started again, with a pause in between that doubles with every failure in a row.
+fn run_watcher<S: MediaSource>(
+    mut source: S,
+    media: &SharedMedia,
+    tx: Sender<SourceEvent>,
+    rx: Receiver<SourceEvent>,
+) {
+    let mut backoff = FIRST_BACKOFF;
+    media.lock().health.backend = Some(source.name());
+    loop {
+        media.lock().health.state = WatcherState::Starting;
+        let failure = match source.start(tx.clone()) {
+            Ok(()) => {
+                backoff = FIRST_BACKOFF;
+                media.lock().health.state = WatcherState::Running;
+                let lost = follow(&mut source, media, &rx);
+                source.stop();
+                lost
+            }
+            Err(err) => {
+                // Whatever the failed start did get to set up is released as well
+                source.stop();
+                Some(err)
+            }
+        };
+        let Some(err) = failure else {
+            break;
+        };
+
+        debug_eprintln!("m_nowplaying: media source failed: {}", err);
+        {
+            let mut state = media.lock();
+            state.health.state = WatcherState::Retrying;
+            state.health.last_error = Some(err.to_string());
+        }
+        if !pause(media, &rx, backoff) {
+            break;
+        }
+        backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+        media.lock().health.restarts += 1;
+    }
+    media.lock().health.state = WatcherState::Stopped;
+}
+
+// Follows the started source until shutdown, or until it reports being lost
+fn follow<S: MediaSource>(
+    source: &mut S,
+    media: &SharedMedia,
+    rx: &Receiver<SourceEvent>,
+) -> Option<SourceError> {
+    // Populate initial state so waiters have an initial baseline
+    if media.is_listening() {
+        refresh(source, media);
+    }
+
+    loop {
+        // Kept up only for queries, and none came for a while
+        if media.idle_in().is_some_and(|left| left.is_zero()) && media.retire_watcher() {
+            return None;
+        }
+        // While a change is settling or a clear is pending, wake up in time for it
+        let wake = match (media.due_in(), media.idle_in()) {
+            (Some(due), Some(idle)) => Some(due.min(idle)),
+            (wake, None) | (None, wake) => wake,
+        };
+        let event = match wake {
+            Some(remaining) => match rx.recv_timeout(remaining) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => {
+                    media.run_due();
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return None,
+            },
+            None => match rx.recv() {
+                Ok(event) => event,
+                Err(_) => return None,
+            },
+        };
+        match event {
+            SourceEvent::Shutdown => return None,
+            SourceEvent::Lost => return Some(SourceError::new("lost the media source")),
+            SourceEvent::CommandQueued => {
+                run_commands(source, media);
+                continue;
+            }
+            SourceEvent::Fetch => {
+                refresh(source, media);
+                media.finish_fetch(true);
+                continue;
+            }
+            // Not from the source
+            SourceEvent::PolicyChanged => {}
+            _ => media.lock().received += 1,
+        }
+        source.on_event(event);
+        if !media.is_listening() {
+            continue;
+        }
+        refresh(source, media);
+    }
+}
+
+// Waits `delay` before the source is started again; false when told to shut down
+// meanwhile, or when nobody needs the watcher any more. Commands fail right away,
+// as there is nothing to run them.
+fn pause(media: &SharedMedia, rx: &Receiver<SourceEvent>, delay: Duration) -> bool {
+    let deadline = Instant::now() + delay;
+    loop {
+        if media.idle_in().is_some_and(|left| left.is_zero()) && media.retire_watcher() {
+            return false;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        let left = media.idle_in().map_or(left, |idle| idle.min(left));
+        match rx.recv_timeout(left) {
+            Ok(SourceEvent::Shutdown) | Err(RecvTimeoutError::Disconnected) => return false,
+            Ok(SourceEvent::CommandQueued) => {
+                while let Some((_, reply)) = media.next_command() {
+                    let _ = reply.send(Err(SourceError::new("media source is not running")));
+                }
+            }
+            Ok(SourceEvent::Fetch) => media.finish_fetch(false),
+            // Player notifications are stale by the restart, and so is a policy
+            // change: the restart reads the player afresh anyway
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+        }
+    }
+}
+
+// Sends queued transport commands to whichever session the policy picks; the
+// player's own notifications then report the effect
+fn run_commands<S: MediaSource>(source: &S, media: &SharedMedia) {
+    while let Some((command, reply)) = media.next_command() {
+        let result = match media.select_session(source.sessions()) {
+            Selection::Backend => source.control(None, command),
+            Selection::Session(app_id) => source.control(Some(&app_id), command),
+            Selection::Nothing => Err(SourceError::new("no media session")),
+        };
+        let _ = reply.send(result);
+    }
+}
+
+// Re-reads the session list and publishes whichever session the policy picks
+fn refresh<S: MediaSource>(source: &S, media: &SharedMedia) {
+    let sessions = source.sessions();
+    let (snapshot, session) = match media.select_session(sessions.clone()) {
+        Selection::Backend => (source.snapshot(), sessions.iter().find(|s| s.current)),
+        Selection::Session(app_id) => (
+            source.session_snapshot(&app_id),
+            sessions.iter().find(|s| s.app_id == app_id),
+        ),
+        Selection::Nothing => (None, None),
+    };
+    media.update_state_with(snapshot, session);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use crate::event::WaitSpec;
+    use crate::source::Command;
+    use crate::source::scripted::scripted;
+    use crate::state::tests::{leaked, track};
+    use crate::state::{
+        FETCH_IDLE, FETCH_REUSE, MediaSnapshot, PlaybackStatus, SessionSnapshot, WaitOutcome,
+    };
+    use crate::timeline::tests::FakeClock;
+
+    // Polls until the state reaches `version`; the watcher applies events on its own thread
+    fn wait_version(media: &SharedMedia, version: u64) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while media.lock().version < version {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting for v{version}"
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    // Tells the watcher to stop the way WatcherSlot::stop does, then waits for it
+    fn shut_down(media: &SharedMedia, watcher: JoinHandle<()>) {
+        assert!(media.notify_watcher(SourceEvent::Shutdown));
+        media.detach_watcher();
+        watcher.join().unwrap();
+    }
+
+    fn wait_until(what: &str, done: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out waiting for {what}");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn initial_snapshot_is_published_when_listening() {
+        let media = leaked();
+        media.set_listening(true);
+        let (source, script) = scripted();
+        script.set(Some(track("Song", "Band")));
+        script.script().sessions = vec![SessionSnapshot {
+            app_id: "Spotify.exe".to_string(),
+            status: Some(PlaybackStatus::Playing),
+            current: true,
+            media: Some(track("Song", "Band")),
+            ..Default::default()
+        }];
+
+        let watcher = spawn_watcher(source, media);
+        wait_version(media, 1);
+        assert_eq!(media.lock().media.title.as_deref(), Some("Song"));
+        assert_eq!(media.lock().sessions, script.script().sessions);
+
+        shut_down(media, watcher);
+        assert_eq!(script.script().stops, 1);
+    }
+
+    #[test]
+    fn events_are_ignored_while_not_listening() {
+        let media = leaked();
+        let (source, script) = scripted();
+        let watcher = spawn_watcher(source, media);
+
+        while script.script().starts == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(script.play(Some(track("Song", "Band"))));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(media.lock().version, 0);
+
+        media.set_listening(true);
+        assert!(script.emit(SourceEvent::SessionSwitched));
+        wait_version(media, 1);
+
+        shut_down(media, watcher);
+        // The source still saw every event so it could keep its subscriptions current
+        assert_eq!(
+            script.script().seen,
+            vec![SourceEvent::PropertiesChanged, SourceEvent::SessionSwitched]
+        );
+        assert_eq!(media.lock().received, 2);
+    }
+
+    #[test]
+    fn track_changes_wake_waiter() {
+        let media = leaked();
+        media.set_listening(true);
+        let (source, script) = scripted();
+        script.set(Some(track("One", "Band")));
+        let watcher = spawn_watcher(source, media);
+        wait_version(media, 1);
+
+        let waiter = thread::spawn(move || media.wait_for_change(WaitSpec::default()));
+        thread::sleep(Duration::from_millis(20));
+        assert!(script.play(Some(track("Two", "Band"))));
+        let WaitOutcome::Changed(change) = waiter.join().unwrap() else {
+            panic!("waiter was cancelled");
+        };
+        assert_eq!(change.to_string(), "2 track title");
+        assert_eq!(media.lock().media.title.as_deref(), Some("Two"));
+
+        // A repeat of the same metadata is not a change
+        assert!(script.play(Some(track("Two", "Band"))));
+        assert!(script.play(None));
+        wait_version(media, 3);
+
+        shut_down(media, watcher);
+        assert_eq!(media.lock().version, 3);
+    }
+
+    #[test]
+    fn pause_and_resume_wake_waiter() {
+        let media = leaked();
+        media.set_listening(true);
+        let (source, script) = scripted();
+        script.set(Some(track("Song", "Band")));
+        script.script().sessions = vec![SessionSnapshot {
+            app_id: "Spotify.exe".to_string(),
+            status: Some(PlaybackStatus::Playing),
+            current: true,
+            media: Some(track("Song", "Band")),
+            ..Default::default()
+        }];
+        let watcher = spawn_watcher(source, media);
+        wait_version(media, 1);
+        assert_eq!(media.lock().status, Some(PlaybackStatus::Playing));
+
+        let waiter = thread::spawn(move || media.wait_for_change(WaitSpec::default()));
+        thread::sleep(Duration::from_millis(20));
+        script.script().sessions[0].status = Some(PlaybackStatus::Paused);
+        assert!(script.emit(SourceEvent::PlaybackChanged));
+        let WaitOutcome::Changed(change) = waiter.join().unwrap() else {
+            panic!("waiter was cancelled");
+        };
+        assert_eq!(change.to_string(), "2 status status");
+        assert_eq!(media.lock().status, Some(PlaybackStatus::Paused));
+        assert_eq!(media.lock().version, 2);
+
+        shut_down(media, watcher);
+    }
+
+    #[test]
+    fn commands_reach_the_selected_session() {
+        let media = leaked();
+        let (source, script) = scripted();
+        script.script().sessions = vec![
+            SessionSnapshot {
+                app_id: "chrome".to_string(),
+                current: true,
+                ..Default::default()
+            },
+            SessionSnapshot {
+                app_id: "Spotify.exe".to_string(),
+                ..Default::default()
+            },
+        ];
+        let watcher = spawn_watcher(source, media);
+        let timeout = Duration::from_secs(5);
+
+        // Commands work without a listener, and go to the backend's pick by default
+        assert_eq!(media.control(Command::Toggle, timeout), Ok(()));
+        media.lock().selector.policy.apply("deny=chrome").unwrap();
+        let seek = Command::Seek(Duration::from_secs(42));
+        assert_eq!(media.control(seek, timeout), Ok(()));
+        assert_eq!(
+            script.script().commands,
+            vec![
+                (None, Command::Toggle),
+                (Some("Spotify.exe".to_string()), seek)
+            ]
+        );
+
+        media
+            .lock()
+            .selector
+            .policy
+            .apply("deny=chrome,spotify")
+            .unwrap();
+        assert_eq!(
+            media.control(Command::Next, timeout),
+            Err(SourceError::new("no media session"))
+        );
+
+        script.script().fail_control = Some(SourceError::new("refused"));
+        media.lock().selector.policy.apply("reset").unwrap();
+        assert_eq!(
+            media.control(Command::Play, timeout),
+            Err(SourceError::new("refused"))
+        );
+        // Commands are not events the source needs to follow
+        assert!(script.script().seen.is_empty());
+
+        shut_down(media, watcher);
+        assert_eq!(
+            media.control(Command::Play, timeout),
+            Err(SourceError::new("media watcher is not running"))
+        );
+    }
+
+    #[test]
+    fn stopped_watcher_unsubscribes_and_starts_again() {
+        let media = leaked();
+        media.set_listening(true);
+        let slot = WatcherSlot::new();
+        let (source, script) = scripted();
+        script.set(Some(track("One", "Band")));
+        slot.start(media, || source);
+        wait_version(media, 1);
+
+        // A running watcher is left alone
+        let (spare, spare_script) = scripted();
+        slot.start(media, || spare);
+        slot.stop(media);
+        assert_eq!(spare_script.script().starts, 0);
+        assert_eq!(script.script().stops, 1);
+        assert!(!script.emit(SourceEvent::PropertiesChanged));
+        assert_eq!(
+            media.control(Command::Play, Duration::from_secs(5)),
+            Err(SourceError::new("media watcher is not running"))
+        );
+
+        let (source, script) = scripted();
+        script.set(Some(track("Two", "Band")));
+        slot.start(media, || source);
+        wait_version(media, 2);
+        assert_eq!(media.lock().media.title.as_deref(), Some("Two"));
+        assert_eq!(media.control(Command::Play, Duration::from_secs(5)), Ok(()));
+
+        slot.stop(media);
+        slot.stop(media);
+        assert_eq!(script.script().stops, 1);
+    }
+
+    #[test]
+    fn failed_start_is_retried_with_backoff() {
+        let media = leaked();
+        media.set_listening(true);
+        let (source, script) = scripted();
+        script.set(Some(track("Song", "Band")));
+        script.script().fail_start = Some(SourceError::new("no backend"));
+        let watcher = spawn_watcher(source, media);
+
+        wait_until("retries", || script.script().starts >= 3);
+        assert_eq!(media.lock().version, 0);
+        assert_eq!(
+            media.lock().health.last_error.as_deref(),
+            Some("no backend")
+        );
+        // Commands fail instead of waiting for a source that is not there
+        assert_eq!(
+            media.control(Command::Play, Duration::from_secs(5)),
+            Err(SourceError::new("media source is not running"))
+        );
+        // And so do reads of the player
+        let asked = Instant::now();
+        assert!(!media.fetch(Duration::from_secs(5)));
+        assert!(asked.elapsed() < Duration::from_secs(1));
+
+        script.script().fail_start = None;
+        wait_version(media, 1);
+        // Every failed start was cleaned up after
+        {
+            let script = script.script();
+            assert_eq!(script.stops, script.starts - 1);
+        }
+        let health = media.lock().health.clone();
+        assert_eq!(health.state, WatcherState::Running);
+        assert!(health.restarts >= 2);
+        assert_eq!(
+            health.to_string(),
+            format!("running {} no backend", health.restarts)
+        );
+
+        shut_down(media, watcher);
+        assert_eq!(media.lock().health.state, WatcherState::Stopped);
+    }
+
+    #[test]
+    fn lost_source_is_started_again() {
+        let media = leaked();
+        media.set_listening(true);
+        let (source, script) = scripted();
+        script.set(Some(track("One", "Band")));
+        let watcher = spawn_watcher(source, media);
+        wait_version(media, 1);
+
+        assert!(script.emit(SourceEvent::Lost));
+        wait_until("the restart", || script.script().starts == 2);
+        assert_eq!(script.script().stops, 1);
+        assert!(script.play(Some(track("Two", "Band"))));
+        wait_version(media, 2);
+        assert_eq!(
+            media.lock().health.to_string(),
+            "running 1 lost the media source"
+        );
+        assert_eq!(media.lock().health.backend, Some("scripted"));
+
+        shut_down(media, watcher);
+        assert_eq!(script.script().stops, 2);
+    }
+
+    #[test]
+    fn fetch_reads_the_player_without_listening() {
+        let clock = Arc::new(FakeClock::new());
+        let media: &'static SharedMedia =
+            Box::leak(Box::new(SharedMedia::with_clock(clock.clone())));
+        let (source, script) = scripted();
+        script.set(Some(track("One", "Band")));
+        let watcher = spawn_watcher(source, media);
+
+        assert!(media.fetch(Duration::from_secs(5)));
+        assert_eq!(media.lock().media.title.as_deref(), Some("One"));
+        assert!(!media.is_listening());
+
+        // Reading the next field right away does not ask the player again
+        script.set(Some(track("Two", "Band")));
+        assert!(media.fetch(Duration::from_secs(5)));
+        assert_eq!(media.lock().media.title.as_deref(), Some("One"));
+        clock.advance(FETCH_REUSE);
+        assert!(media.fetch(Duration::from_secs(5)));
+        assert_eq!(media.lock().media.title.as_deref(), Some("Two"));
+        assert_eq!(media.lock().fetches, 2);
+
+        shut_down(media, watcher);
+        clock.advance(FETCH_REUSE);
+        assert!(!media.fetch(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn idle_query_watcher_ends_and_starts_again() {
+        let clock = Arc::new(FakeClock::new());
+        let media: &'static SharedMedia =
+            Box::leak(Box::new(SharedMedia::with_clock(clock.clone())));
+        let slot = WatcherSlot::new();
+        let (source, script) = scripted();
+        script.set(Some(track("One", "Band")));
+        media.note_query();
+        slot.start(media, || source);
+        assert!(media.fetch(Duration::from_secs(5)));
+
+        // Woken by any event once nobody has asked for a while, it lets go
+        clock.advance(FETCH_IDLE);
+        assert!(script.emit(SourceEvent::PlaybackChanged));
+        wait_until("the idle watcher to end", || {
+            media.lock().health.state == WatcherState::Stopped
+        });
+        assert_eq!(script.script().stops, 1);
+        assert!(!media.has_watcher());
+
+        // The next query brings up a new one
+        let (source, script) = scripted();
+        script.set(Some(track("Two", "Band")));
+        media.note_query();
+        slot.start(media, || source);
+        clock.advance(FETCH_REUSE);
+        assert!(media.fetch(Duration::from_secs(5)));
+        assert_eq!(media.lock().media.title.as_deref(), Some("Two"));
+
+        slot.stop(media);
+        assert_eq!(script.script().stops, 1);
+    }
+
+    #[test]
+    fn policy_change_reselects_immediately() {
+        let media = leaked();
+        media.set_listening(true);
+        let (source, script) = scripted();
+        script.set(Some(track("Video", "Tab")));
+        script.script().sessions = vec![
+            SessionSnapshot {
+                app_id: "chrome".to_string(),
+                status: Some(PlaybackStatus::Playing),
+                current: true,
+                media: Some(track("Video", "Tab")),
+                ..Default::default()
+            },
+            SessionSnapshot {
+                app_id: "Spotify.exe".to_string(),
+                status: Some(PlaybackStatus::Paused),
+                current: false,
+                media: Some(track("Song", "Band")),
+                ..Default::default()
+            },
+        ];
+        let watcher = spawn_watcher(source, media);
+        wait_version(media, 1);
+        assert_eq!(media.lock().media.title.as_deref(), Some("Video"));
+
+        media.lock().selector.policy.apply("deny=chrome").unwrap();
+        assert!(media.notify_watcher(SourceEvent::PolicyChanged));
+        wait_version(media, 2);
+        assert_eq!(media.lock().media.title.as_deref(), Some("Song"));
+
+        shut_down(media, watcher);
+    }
+
+    #[test]
+    fn bursts_are_published_once_settled() {
+        let clock = Arc::new(FakeClock::new());
+        let media: &'static SharedMedia =
+            Box::leak(Box::new(SharedMedia::with_clock(clock.clone())));
+        media.lock().settle = Duration::from_millis(50);
+        media.set_listening(true);
+        let (source, script) = scripted();
+        let watcher = spawn_watcher(source, media);
+        while script.script().starts == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Title first, then artist, then artwork, as players tend to report a new track
+        let mut burst = MediaSnapshot {
+            title: Some("Song".to_string()),
+            ..Default::default()
+        };
+        assert!(script.play(Some(burst.clone())));
+        burst.artist = Some("Band".to_string());
+        assert!(script.play(Some(burst.clone())));
+        burst.thumbnail_bytes = Some(vec![1]);
+        assert!(script.play(Some(burst)));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while media.lock().media.thumbnail_bytes.is_none() {
+            assert!(Instant::now() < deadline, "burst was never applied");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(media.lock().version, 0);
+
+        clock.advance(Duration::from_millis(50));
+        wait_version(media, 1);
+        assert_eq!(
+            media.lock().events.latest().unwrap().to_string(),
+            "1 track title,artist,thumbnail"
+        );
+
+        shut_down(media, watcher);
+        assert_eq!(media.lock().version, 1);
+    }
+}